@@ -2,10 +2,8 @@ extern crate env_logger;
 extern crate firmata;
 extern crate serial;
 
-use firmata::FirmataMsg;
 use firmata::connection::Connection;
-use firmata::errors::*;
-use std::{thread, time};
+use std::time;
 
 fn print_capabilities(vec: Vec<Vec<firmata::protocol::PinCapability>>) {
     println!("Pin capabilities:");
@@ -26,19 +24,10 @@ fn run() -> firmata::errors::Result<()> {
     let device = "/dev/ttyACM0";
     let mut conn = Connection::open(device)?;
     conn.resync()?;
-    conn.capabilities()?;
 
-    for _ in 0..10 {
-        match conn.read() {
-            Ok(FirmataMsg::CapabilityResponse(vec)) => {
-                print_capabilities(vec);
-                return Ok(());
-            }
-            Ok(_) => { },
-            Err(e) => return Err(e.into()),
-        }
-    }
-    Err(ErrorKind::UnexpectedResponse.into())
+    let capabilities = conn.query_capabilities(time::Duration::from_secs(1))?;
+    print_capabilities(capabilities);
+    Ok(())
 }
 
 fn main() {
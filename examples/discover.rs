@@ -0,0 +1,36 @@
+extern crate env_logger;
+extern crate firmata;
+
+use firmata::discovery;
+
+fn main() {
+    env_logger::init();
+
+    let boards = discovery::discover();
+    if boards.is_empty() {
+        println!("No Firmata boards found.");
+        return;
+    }
+
+    for board in boards {
+        println!(
+            "{}: {} v{}.{}",
+            board.port.path.display(),
+            board.firmware.name,
+            board.firmware.major,
+            board.firmware.minor
+        );
+        if let Some(ref protocol) = board.protocol {
+            println!("\tprotocol: v{}.{}", protocol.0, protocol.1);
+        }
+        if let Some(ref usb) = board.port.usb {
+            println!("\tusb: {:04x}:{:04x}", usb.vendor_id, usb.product_id);
+        }
+        if let Some(serial) = board.serial_number() {
+            println!("\tserial: {}", serial);
+        }
+        if let Some(ref link) = board.port.by_id {
+            println!("\tby-id: {}", link.display());
+        }
+    }
+}
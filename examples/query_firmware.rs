@@ -2,10 +2,8 @@ extern crate env_logger;
 extern crate firmata;
 extern crate serial;
 
-use firmata::FirmataMsg;
 use firmata::connection::Connection;
-use firmata::errors::*;
-use std::{str, thread, time};
+use std::time;
 
 fn run() -> firmata::errors::Result<()> {
     env_logger::init();
@@ -13,25 +11,15 @@ fn run() -> firmata::errors::Result<()> {
     let device = "/dev/ttyACM0";
     let mut conn = Connection::open(device)?;
     conn.resync()?;
-    conn.query_firmware()?;
 
-    match conn.read() {
-        Ok(FirmataMsg::QueryFirmware {
-            major,
-            minor,
-            firmware_name,
-        }) => {
-            println!(
-                "Firmware query: Firmata v{}.{} '{}'",
-                major,
-                minor,
-                str::from_utf8(&firmware_name).unwrap()
-            );
-            Ok(())
-        }
-        Ok(_) => Err(ErrorKind::UnexpectedResponse.into()),
-        Err(e) => Err(e.into()),
-    }
+    let firmware = conn.query_firmware_info(time::Duration::from_secs(1))?;
+    println!(
+        "Firmware query: Firmata v{}.{} '{}'",
+        firmware.major,
+        firmware.minor,
+        firmware.name
+    );
+    Ok(())
 }
 
 fn main() {
@@ -3,6 +3,7 @@ extern crate firmata;
 extern crate serial;
 
 use firmata::errors::*;
+use firmata::events::{Event, EventKind, Filter};
 use firmata::protocol::PinMode;
 use firmata::connection::Connection;
 use std::{thread, time};
@@ -30,17 +31,16 @@ fn run() -> Result<()> {
 
     let mux = Arc::new(Mutex::new(conn));
     let handle = firmata::worker::Worker::spawn(&mux);
+    let changes = handle.subscribe(Filter::new().kind(EventKind::PinChanged));
 
-    for _ in 0..100 {
-        {
-            let ref conn = mux.lock().unwrap();
-            let ref pins = conn.board().unwrap().pins;
-            println!("Analog value: {:?}", pins);
+    for event in changes.iter().take(100) {
+        if let Event::PinChanged { pin, value } = event {
+            println!("Analog value: A{} = {}", pin, value);
         }
-        thread::sleep(time::Duration::from_millis(50));
     }
 
     mux.lock().unwrap().digital_port_write(port, 0)?;
+    handle.shutdown().expect("Worker thread panicked");
     Ok(())
 }
 
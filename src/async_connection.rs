@@ -0,0 +1,505 @@
+//! An asynchronous connection to a Firmata board for use with tokio.
+//!
+//! This mirrors `connection::Connection`: every command returns a future
+//! that writes and flushes the same bytes as the matching `client`
+//! function, and reads are applied to the `Board` held by the connection.
+
+use ::board::{Board, Firmware, Protocol};
+use ::codec::FirmataCodec;
+use ::errors::*;
+use ::events::Event;
+use ::protocol::{FirmataMsg, PinCapability, PinMode, PinState};
+use futures::{future, FutureExt, Stream, StreamExt, TryFutureExt};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Sleep;
+use tokio_util::codec::Framed;
+
+/// How many times `resync` resets the device before giving up.
+const RESYNC_ATTEMPTS: usize = 5;
+
+/// How long `resync` waits for the device to answer each reset.
+const RESYNC_TIMEOUT: time::Duration = time::Duration::from_millis(600);
+
+pub struct AsyncConnection<T> {
+    framed: Framed<T, FirmataCodec>,
+    board: Board,
+    out: Vec<u8>,
+}
+
+impl<T> AsyncConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin
+{
+    pub fn new(inner: T) -> Self {
+        AsyncConnection {
+            framed: Framed::new(inner, FirmataCodec::new()),
+            board: Board::default(),
+            out: Vec::new(),
+        }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.framed.get_ref()
+    }
+
+    pub fn reset(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, ::client::reset)
+    }
+
+    pub fn query_firmware(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, ::client::query_firmware)
+    }
+
+    pub fn query_version(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, ::client::query_version)
+    }
+
+    pub fn capabilities(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, ::client::capabilities)
+    }
+
+    pub fn analog_mapping(&mut self) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, ::client::analog_mapping)
+    }
+
+    pub fn pin_state(&mut self, pin: u8) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, move |buf| ::client::pin_state(buf, pin))
+    }
+
+    pub fn set_pin_mode(&mut self, pin: u8, mode: PinMode) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        write(framed, out, move |buf| ::client::set_pin_mode(buf, pin, mode))
+            .map_ok(move |()| board.record_mode(pin, mode))
+    }
+
+    pub fn analog_report(&mut self, pin: u8, state: bool) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        write(framed, out, move |buf| ::client::analog_report(buf, pin, state))
+            .map_ok(move |()| board.record_analog_report(pin, state))
+    }
+
+    pub fn digital_report(&mut self, port: u8, state: bool) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        write(framed, out, move |buf| ::client::digital_report(buf, port, state))
+            .map_ok(move |()| board.record_digital_report(port, state))
+    }
+
+    pub fn digital_port_write(&mut self, port: u8, value: u8) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        write(framed, out, move |buf| ::client::digital_port_write(buf, port, value))
+            .map_ok(move |()| board.record_port_write(port, value))
+    }
+
+    /// Set the level of a single digital pin, as `Connection::digital_write` does.
+    pub fn digital_write(&mut self, pin: u8, level: bool) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        let pin_write = board.supports_pin_write();
+        let port_value = board.port_value_with(pin, level);
+        write(framed, out, move |buf| {
+            if pin_write {
+                ::client::digital_write(buf, pin, level)
+            } else {
+                ::client::digital_port_write(buf, pin / 8, port_value)
+            }
+        }).map_ok(move |()| board.record_pin_write(pin, level))
+    }
+
+    pub fn analog_write(&mut self, pin: u8, value: u16) -> impl Future<Output = Result<()>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut out, ref mut board } = *self;
+        write(framed, out, move |buf| ::client::analog_write(buf, pin, value))
+            .map_ok(move |()| board.record_analog_write(pin, value))
+    }
+
+    pub fn i2c_config(&mut self, delay_us: u16) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, move |buf| ::client::i2c_config(buf, delay_us))
+    }
+
+    pub fn i2c_write(&mut self, address: u8, data: &[u8]) -> impl Future<Output = Result<()>> + '_ {
+        let data = data.to_vec();
+        write(&mut self.framed, &mut self.out, move |buf| ::client::i2c_write(buf, address, &data))
+    }
+
+    pub fn i2c_read(&mut self, address: u8, register: Option<u16>, len: u16) -> impl Future<Output = Result<()>> + '_ {
+        write(&mut self.framed, &mut self.out, move |buf| ::client::i2c_read(buf, address, register, len))
+    }
+
+    /// Read from an I2C device and wait for the reply, as
+    /// `Connection::query_i2c_read` does.
+    pub fn query_i2c_read(
+        &mut self,
+        address: u8,
+        register: Option<u16>,
+        len: u16,
+        timeout: time::Duration,
+    ) -> impl Future<Output = Result<Vec<u8>>> + '_ {
+        self.query(
+            move |buf| ::client::i2c_read(buf, address, register, len),
+            timeout,
+            move |msg| match *msg {
+                FirmataMsg::I2cReply { address: from, ref data, .. } if from == u16::from(address) => Some(data.clone()),
+                _ => None,
+            },
+        )
+    }
+
+    /// Query the capabilities of every pin and wait for the response.
+    pub fn query_capabilities(&mut self, timeout: time::Duration) -> impl Future<Output = Result<Vec<Vec<PinCapability>>>> + '_ {
+        self.query(::client::capabilities, timeout, |msg| match *msg {
+            FirmataMsg::CapabilityResponse(ref pins) => Some(pins.clone()),
+            _ => None,
+        })
+    }
+
+    /// Query the firmware name and version and wait for the response.
+    pub fn query_firmware_info(&mut self, timeout: time::Duration) -> impl Future<Output = Result<Firmware>> + '_ {
+        self.query(::client::query_firmware, timeout, |msg| match *msg {
+            FirmataMsg::QueryFirmware { major, minor, ref firmware_name } => Some(Firmware {
+                major,
+                minor,
+                name: String::from_utf8_lossy(firmware_name).into(),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Query the protocol version and wait for the response.
+    pub fn query_protocol_version(&mut self, timeout: time::Duration) -> impl Future<Output = Result<Protocol>> + '_ {
+        self.query(::client::query_version, timeout, |msg| match *msg {
+            FirmataMsg::ProtocolVersion { major, minor } => Some(Protocol(major, minor)),
+            _ => None,
+        })
+    }
+
+    /// Query the analog channel of every pin and wait for the response.
+    pub fn query_analog_mapping(&mut self, timeout: time::Duration) -> impl Future<Output = Result<Vec<Option<u8>>>> + '_ {
+        self.query(::client::analog_mapping, timeout, |msg| match *msg {
+            FirmataMsg::AnalogMappingResponse(ref channels) => Some(channels.clone()),
+            _ => None,
+        })
+    }
+
+    /// Query the mode and state of a pin and wait for the response.
+    pub fn query_pin_state(&mut self, pin: u8, timeout: time::Duration) -> impl Future<Output = Result<PinState>> + '_ {
+        self.query(move |buf| ::client::pin_state(buf, pin), timeout, move |msg| match *msg {
+            FirmataMsg::PinStateResponse(ref state) if state.pin == pin => Some(state.clone()),
+            _ => None,
+        })
+    }
+
+    /// Send a request, then read messages until `select` picks a response
+    /// out of one, or until `timeout` passes.
+    ///
+    /// Every message read, including the response, is applied to the board,
+    /// as `Connection` does while waiting.
+    fn query<'a, E, F, R>(&'a mut self, encode: E, timeout: time::Duration, mut select: F) -> impl Future<Output = Result<R>> + 'a
+    where
+        E: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+        F: FnMut(&FirmataMsg) -> Option<R> + 'a,
+        R: 'a
+    {
+        self.out.clear();
+        let encoded = encode(&mut self.out);
+        let mut written = 0;
+        let mut deadline: Option<Pin<Box<Sleep>>> = None;
+        let this = self;
+        future::ready(encoded).err_into().and_then(move |()| future::poll_fn(move |cx| {
+            let AsyncConnection { ref mut framed, ref mut board, ref out } = *this;
+            match poll_send(framed.get_mut(), out, &mut written, cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+            loop {
+                match Pin::new(&mut *framed).poll_next(cx) {
+                    Poll::Ready(Some(Ok(msg))) => {
+                        let response = select(&msg);
+                        board.update(msg);
+                        if let Some(response) = response {
+                            return Poll::Ready(Ok(response));
+                        }
+                    }
+                    Poll::Ready(Some(Err(e))) => trace!("Read returned error {:?}", e),
+                    Poll::Ready(None) => return Poll::Ready(Err(ErrorKind::ConnectionClosed.into())),
+                    Poll::Pending => {
+                        let deadline = deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                        return match deadline.as_mut().poll(cx) {
+                            Poll::Ready(()) => Poll::Ready(Err(ErrorKind::ResponseTimeout.into())),
+                            Poll::Pending => Poll::Pending,
+                        };
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Read the next message from the device without applying it to the board.
+    pub fn read(&mut self) -> impl Future<Output = Result<FirmataMsg>> + '_ {
+        self.framed.next().map(closed_if_none)
+    }
+
+    /// Read a message from the device and apply it to the board, returning
+    /// the events it caused.
+    pub fn update(&mut self) -> impl Future<Output = Result<Vec<Event>>> + '_ {
+        let AsyncConnection { ref mut framed, ref mut board, .. } = *self;
+        framed.next().map(closed_if_none).map_ok(move |msg| board.update(msg))
+    }
+
+    /// Resynchronize with the device, as `client::resync` does.
+    pub fn resync(&mut self) -> Resync<'_, T> {
+        Resync { conn: self, attempt: 0, written: None, deadline: None }
+    }
+
+    /// A stream of the events caused by each message from the device. The
+    /// stream yields `Event::Disconnected` and ends when the device does.
+    pub fn events(&mut self) -> EventStream<'_, T> {
+        EventStream { conn: self, pending: VecDeque::new(), done: false }
+    }
+}
+
+/// Encode a command into `out` with one of the `client` functions, write
+/// it and flush it.
+fn write<'a, T, F>(
+    framed: &'a mut Framed<T, FirmataCodec>,
+    out: &'a mut Vec<u8>,
+    encode: F,
+) -> impl Future<Output = Result<()>> + 'a
+where
+    T: AsyncWrite + Unpin,
+    F: FnOnce(&mut Vec<u8>) -> io::Result<()>
+{
+    out.clear();
+    let encoded = encode(out);
+    let out: &'a Vec<u8> = out;
+    let mut written = 0;
+    future::ready(encoded)
+        .and_then(move |()| future::poll_fn(move |cx| poll_send(framed.get_mut(), out, &mut written, cx)))
+        .err_into()
+}
+
+/// Write what is left of `out` after the first `written` bytes, then flush.
+fn poll_send<T>(inner: &mut T, out: &[u8], written: &mut usize, cx: &mut Context) -> Poll<io::Result<()>>
+where
+    T: AsyncWrite + Unpin
+{
+    while *written < out.len() {
+        match Pin::new(&mut *inner).poll_write(cx, &out[*written..]) {
+            Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+            Poll::Ready(Ok(n)) => *written += n,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+    Pin::new(inner).poll_flush(cx)
+}
+
+fn closed_if_none(item: Option<Result<FirmataMsg>>) -> Result<FirmataMsg> {
+    item.unwrap_or_else(|| Err(ErrorKind::ConnectionClosed.into()))
+}
+
+/// The future returned by `AsyncConnection::resync`.
+pub struct Resync<'a, T: 'a> {
+    conn: &'a mut AsyncConnection<T>,
+    attempt: usize,
+    /// How much of the reset request has been written in this attempt.
+    written: Option<usize>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a, T> Future for Resync<'a, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin
+{
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.written.is_none() {
+                debug!(
+                    "Attempting to resync Firmata connection ({} of {})",
+                    this.attempt + 1,
+                    RESYNC_ATTEMPTS
+                );
+                this.conn.out.clear();
+                ::client::reset(&mut this.conn.out)?;
+                ::client::query_firmware(&mut this.conn.out)?;
+                this.written = Some(0);
+                this.deadline = Some(Box::pin(tokio::time::sleep(RESYNC_TIMEOUT)));
+            }
+
+            // Finish sending the reset request before listening for the reply.
+            if let Some(ref mut written) = this.written {
+                match poll_send(this.conn.framed.get_mut(), &this.conn.out, written, cx) {
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            match Pin::new(&mut this.conn.framed).poll_next(cx) {
+                Poll::Ready(Some(Ok(msg @ FirmataMsg::ProtocolVersion { .. }))) |
+                Poll::Ready(Some(Ok(msg @ FirmataMsg::QueryFirmware { .. }))) => {
+                    debug!("Firmata connection resynchronized.");
+                    this.conn.board.update(msg);
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Ready(Some(Ok(msg))) => trace!("Discarding message {:?}", msg),
+                Poll::Ready(Some(Err(e))) => trace!("Read returned error {:?}", e),
+                Poll::Ready(None) => return Poll::Ready(Err(ErrorKind::ConnectionClosed.into())),
+                Poll::Pending => {
+                    let expired = this.deadline
+                        .as_mut()
+                        .is_none_or(|deadline| deadline.as_mut().poll(cx).is_ready());
+                    if !expired {
+                        return Poll::Pending;
+                    }
+                    this.attempt += 1;
+                    if this.attempt >= RESYNC_ATTEMPTS {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::NotConnected,
+                            "Could not resynchronize Firmata connection",
+                        ).into()));
+                    }
+                    this.written = None;
+                }
+            }
+        }
+    }
+}
+
+/// The stream returned by `AsyncConnection::events`.
+pub struct EventStream<'a, T: 'a> {
+    conn: &'a mut AsyncConnection<T>,
+    pending: VecDeque<Event>,
+    done: bool,
+}
+
+impl<'a, T> Stream for EventStream<'a, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin
+{
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Event>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut this.conn.framed).poll_next(cx) {
+                Poll::Ready(Some(Ok(msg))) => this.pending.extend(this.conn.board.update(msg)),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    this.done = true;
+                    this.pending.push_back(Event::Disconnected);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::*;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, BufWriter};
+    use tokio::runtime;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn sends_commands_and_records_them() {
+        let (host, mut device) = duplex(64);
+        let mut conn = AsyncConnection::new(host);
+
+        block_on(conn.analog_report(3, true)).unwrap();
+        block_on(conn.digital_port_write(1, 0x81)).unwrap();
+
+        let mut sent = [0; 5];
+        block_on(device.read_exact(&mut sent)).unwrap();
+        assert_eq!(sent, [ANALOG_REPORT | 3, 1, DIGITAL_MESSAGE << 4 | 1, 0x01, 0x01]);
+        assert!(conn.board().analog_reports.contains(&3));
+        assert!(block_on(conn.analog_report(16, true)).is_err());
+    }
+
+    #[test]
+    fn resyncs_and_streams_events() {
+        let (host, mut device) = duplex(64);
+        let mut conn = AsyncConnection::new(host);
+
+        block_on(device.write_all(b"\xE0\x10\x01\xF9\x02\x06\xE2\x05\x00")).unwrap();
+        block_on(conn.resync()).unwrap();
+        assert!(conn.board().protocol.is_some());
+
+        let mut request = [0; 4];
+        block_on(device.read_exact(&mut request)).unwrap();
+        assert_eq!(request, [RESET, START_SYSEX, QUERY_FIRMWARE, END_SYSEX]);
+
+        drop(device);
+        let events: Vec<_> = block_on(conn.events().collect::<Vec<_>>());
+        assert_eq!(
+            events.into_iter().map(|e| e.unwrap()).collect::<Vec<_>>(),
+            vec![Event::PinChanged { pin: 2, value: 5 }, Event::Disconnected]
+        );
+    }
+
+    #[test]
+    fn flushes_buffered_writes() {
+        let (host, mut device) = duplex(64);
+        let mut conn = AsyncConnection::new(BufWriter::new(host));
+
+        block_on(conn.set_pin_mode(13, PinMode::DigitalOutput)).unwrap();
+        // Dropping the buffer discards what wasn't flushed.
+        drop(conn);
+
+        let mut sent = Vec::new();
+        block_on(device.read_to_end(&mut sent)).unwrap();
+        assert_eq!(sent, [SET_PIN_MODE, 13, 1]);
+    }
+
+    #[test]
+    fn queries_wait_for_responses() {
+        let (host, mut device) = duplex(256);
+        let mut conn = AsyncConnection::new(host);
+        let timeout = time::Duration::from_millis(100);
+
+        let reply = FirmataMsg::I2cReply { address: 0x48, register: 0, data: vec![0x12, 0x34] };
+        block_on(device.write_all(&FirmataMsg::AnalogRead { pin: 0, value: 7 }.encode())).unwrap();
+        block_on(device.write_all(&reply.encode())).unwrap();
+        let data = block_on(conn.query_i2c_read(0x48, Some(0), 2, timeout)).unwrap();
+        assert_eq!(data, [0x12, 0x34]);
+        assert_eq!(conn.board().pins[&0].value, 7);
+
+        let mut sent = [0; 9];
+        block_on(device.read_exact(&mut sent)).unwrap();
+        assert_eq!(sent, [START_SYSEX, I2C_REQUEST, 0x48, I2C_MODE_READ_ONCE, 0, 0, 2, 0, END_SYSEX]);
+
+        block_on(device.write_all(&FirmataMsg::AnalogMappingResponse(vec![None, Some(0)]).encode())).unwrap();
+        assert_eq!(block_on(conn.query_analog_mapping(timeout)).unwrap(), vec![None, Some(0)]);
+
+        match block_on(conn.query_pin_state(3, timeout)) {
+            Err(Error(ErrorKind::ResponseTimeout, _)) => {}
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
+}
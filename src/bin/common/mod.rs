@@ -0,0 +1,96 @@
+//! Opening the board named on the command line, shared by the `firmata`
+//! and `firmata-dashboard` binaries.
+
+use firmata::board::Board;
+use firmata::connection::{Connection, ConnectionBuilder, InitReport};
+use firmata::discovery;
+use firmata::errors::*;
+use firmata::tcp::TcpPort;
+use serial_unix::TTYPort;
+
+/// Where to find the board to connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// The first board found by `discovery::discover_with`.
+    Discover,
+    /// The board on a serial port.
+    Serial(String),
+    /// An EthernetFirmata or WiFiFirmata board at an address.
+    Tcp(String),
+}
+
+/// An open connection over either transport.
+pub enum Link {
+    Serial(Connection<TTYPort>),
+    Tcp(Connection<TcpPort>),
+}
+
+impl Link {
+    pub fn board(&self) -> Option<&Board> {
+        match *self {
+            Link::Serial(ref conn) => conn.board(),
+            Link::Tcp(ref conn) => conn.board(),
+        }
+    }
+}
+
+/// A board opened and set up by `connect`.
+pub struct Connected {
+    pub link: Link,
+    /// The port path or address the board was opened at.
+    pub source: String,
+    /// The queries the board left unanswered during `Connection::setup`.
+    pub report: InitReport,
+}
+
+impl Connected {
+    /// Tell the user which board discovery picked for `target`, and which
+    /// queries went unanswered during setup.
+    pub fn announce(&self, target: &Target) {
+        if *target == Target::Discover {
+            let firmware = self.link.board().and_then(|board| board.firmware.as_ref());
+            let name = firmware.map_or("a board", |firmware| firmware.name.as_str());
+            eprintln!("Using {} on {}", name, self.source);
+        }
+        if !self.report.is_complete() {
+            eprintln!("warning: the board did not answer {:?}", self.report.unanswered);
+        }
+    }
+}
+
+/// Open the board at `target`, using `builder` for serial ports, and set
+/// it up with `Connection::setup`.
+pub fn connect(target: &Target, builder: &ConnectionBuilder) -> Result<Connected> {
+    let (mut link, source) = match *target {
+        Target::Serial(ref path) => (Link::Serial(builder.open(path)?), path.clone()),
+        Target::Tcp(ref addr) => (Link::Tcp(Connection::connect_tcp(addr.as_str())?), addr.clone()),
+        Target::Discover => {
+            let found = discovery::discover_with(builder);
+            let board = found.first().ok_or("No Firmata boards found on the serial ports")?;
+            let path = board.port.path.to_str().ok_or("Invalid port path")?;
+            (Link::Serial(builder.open(path)?), path.to_string())
+        }
+    };
+    let report = match link {
+        Link::Serial(ref mut conn) => conn.setup()?,
+        Link::Tcp(ref mut conn) => conn.setup()?,
+    };
+    Ok(Connected { link, source, report })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use firmata::emulator::Emulator;
+    use firmata::pty::VirtualBoard;
+
+    #[test]
+    fn connects_to_a_serial_target() {
+        let board = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let path = board.path().to_str().unwrap().to_string();
+        let connected = connect(&Target::Serial(path.clone()), &ConnectionBuilder::new()).unwrap();
+        assert!(connected.report.is_complete(), "{:?}", connected.report);
+        assert_eq!(connected.source, path);
+        assert_eq!(connected.link.board().unwrap().pin_count(), 20);
+    }
+}
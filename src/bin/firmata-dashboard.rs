@@ -0,0 +1,427 @@
+//! A terminal dashboard showing the live state of every pin of a board.
+//!
+//! ```text
+//! firmata-dashboard [--port PATH [--baud RATE] | --tcp ADDR]
+//! ```
+//!
+//! Keys: up and down select a pin, `m` cycles its mode, space toggles a
+//! digital output, `+` and `-` change a PWM or servo value, `r` toggles
+//! reporting and `q` quits.
+
+extern crate firmata;
+extern crate ratatui;
+extern crate serial_unix;
+
+mod common;
+
+use common::{Link, Target};
+use firmata::board::{Board, Pin};
+use firmata::connection::{Connection, ConnectionBuilder, RW};
+use firmata::errors::*;
+use firmata::events::Events;
+use firmata::history::Limit;
+use firmata::protocol::PinMode;
+use firmata::split::{Split, Writer};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use std::env;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "usage: firmata-dashboard [--port PATH [--baud RATE] | --tcp ADDR]";
+
+/// How many reported values each sparkline shows.
+const HISTORY_SAMPLES: usize = 40;
+
+/// How often the screen is redrawn while no key is pressed.
+const REFRESH: Duration = Duration::from_millis(50);
+
+const SPARKS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The change made by one press of `+` or `-`.
+const SERVO_STEP: i32 = 10;
+const PWM_STEPS: i32 = 16;
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<(Target, usize)> {
+    let (mut target, mut baud) = (Target::Discover, 57600);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(USAGE)?;
+        match arg.as_str() {
+            "--port" => target = Target::Serial(value),
+            "--tcp" => target = Target::Tcp(value),
+            "--baud" => baud = value.parse().map_err(|_| USAGE)?,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok((target, baud))
+}
+
+/// A pin shown on the dashboard.
+struct PinRow {
+    number: u8,
+    pin: Pin,
+    digital_reporting: bool,
+    level: Option<bool>,
+}
+
+struct Dashboard<W>
+where
+    W: io::Write + Send
+{
+    writer: Writer<W>,
+    board: Arc<Mutex<Board>>,
+    table: TableState,
+    status: String,
+}
+
+impl<W> Dashboard<W>
+where
+    W: io::Write + Send
+{
+    /// The pins that can be used, with the state needed to draw them.
+    fn rows(&self) -> Vec<PinRow> {
+        let board = self.board.lock().unwrap();
+        let mut numbers: Vec<u8> = board
+            .pins
+            .iter()
+            .filter(|&(_, pin)| pin.capabilities.as_ref().is_some_and(|modes| !modes.is_empty()))
+            .map(|(&number, _)| number)
+            .collect();
+        numbers.sort();
+        numbers
+            .into_iter()
+            .map(|number| PinRow {
+                number,
+                pin: board.pins[&number].clone(),
+                digital_reporting: board.digital_reports.contains(&(number / 8)),
+                level: board.digital_read(number),
+            })
+            .collect()
+    }
+
+    fn draw(&mut self, frame: &mut Frame, rows: &[PinRow], title: &str) {
+        let [table_area, status_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+        let header = Row::new(vec!["pin", "mode", "value", "reporting", "history"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let lines = rows.iter().map(|row| {
+            let label = match row.pin.analog_channel {
+                Some(channel) => format!("{} (A{})", row.number, channel),
+                None => row.number.to_string(),
+            };
+            let mode = row.pin.mode.map(PinMode::name).unwrap_or_else(|| "?".into());
+            let reporting = match row.pin.mode {
+                Some(PinMode::AnalogInput) => row.pin.reporting,
+                Some(mode) if mode.is_digital_input() => row.digital_reporting,
+                _ => false,
+            };
+            Row::new(vec![
+                label,
+                mode,
+                value(row),
+                if reporting { "on".into() } else { "".into() },
+                sparkline(&row.pin),
+            ])
+        });
+        let widths = [
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Length(9),
+            Constraint::Min(HISTORY_SAMPLES as u16),
+        ];
+        let table = Table::new(lines, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+        frame.render_widget(Paragraph::new(Line::from(self.status.as_str())), status_area);
+    }
+
+    /// Handle a key, returning false when the dashboard should close.
+    fn key(&mut self, code: KeyCode, rows: &[PinRow]) -> bool {
+        let selected = self.table.selected().and_then(|index| rows.get(index));
+        let result = match (code, selected) {
+            (KeyCode::Char('q'), _) | (KeyCode::Esc, _) => return false,
+            (KeyCode::Down, _) | (KeyCode::Char('j'), _) => {
+                self.table.select_next();
+                Ok(())
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('k'), _) => {
+                self.table.select_previous();
+                Ok(())
+            }
+            (KeyCode::Char('m'), Some(row)) => self.next_mode(row),
+            (KeyCode::Char(' '), Some(row)) => self.toggle_output(row),
+            (KeyCode::Char('+'), Some(row)) => self.adjust(row, 1),
+            (KeyCode::Char('-'), Some(row)) => self.adjust(row, -1),
+            (KeyCode::Char('r'), Some(row)) => self.toggle_reporting(row),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.status = format!("error: {}", e);
+        }
+        true
+    }
+
+    /// Switch a pin to the next mode it supports, reporting inputs.
+    fn next_mode(&mut self, row: &PinRow) -> Result<()> {
+        let next = next_mode(&row.pin).ok_or_else(|| format!("pin {} has no modes", row.number))?;
+        self.writer.set_pin_mode(row.number, next)?;
+        if let Some(channel) = row.pin.analog_channel {
+            self.writer.analog_report(channel, next == PinMode::AnalogInput)?;
+        }
+        if next.is_digital_input() {
+            self.writer.digital_report(row.number / 8, true)?;
+        }
+        self.status = format!("pin {} set to {}", row.number, next.name());
+        Ok(())
+    }
+
+    fn toggle_output(&mut self, row: &PinRow) -> Result<()> {
+        if row.pin.mode != Some(PinMode::DigitalOutput) {
+            return Err(format!("pin {} is not an output", row.number).into());
+        }
+        let level = row.pin.written != Some(1);
+        self.writer.digital_write(row.number, level)?;
+        self.status = format!("pin {} set {}", row.number, if level { "high" } else { "low" });
+        Ok(())
+    }
+
+    /// Step a PWM duty cycle or servo angle up or down.
+    fn adjust(&mut self, row: &PinRow, direction: i32) -> Result<()> {
+        let value = adjusted(row.number, &row.pin, direction)?;
+        self.writer.analog_write(row.number, value)?;
+        self.status = format!("pin {} set to {}", row.number, value);
+        Ok(())
+    }
+
+    fn toggle_reporting(&mut self, row: &PinRow) -> Result<()> {
+        match (row.pin.mode, row.pin.analog_channel) {
+            (Some(PinMode::AnalogInput), Some(channel)) => self.writer.analog_report(channel, !row.pin.reporting),
+            (Some(mode), _) if mode.is_digital_input() => {
+                self.writer.digital_report(row.number / 8, !row.digital_reporting)
+            }
+            _ => Err(format!("pin {} is not an input", row.number).into()),
+        }
+    }
+}
+
+/// The mode after a pin's current one among those it supports.
+fn next_mode(pin: &Pin) -> Option<PinMode> {
+    let modes: Vec<PinMode> = pin.capabilities.iter().flatten().map(|cap| cap.mode).collect();
+    match pin.mode.and_then(|mode| modes.iter().position(|&m| m == mode)) {
+        Some(index) => Some(modes[(index + 1) % modes.len()]),
+        None => modes.first().cloned(),
+    }
+}
+
+/// The value one press of `+` (`direction` 1) or `-` (-1) moves a PWM or
+/// servo pin to.
+fn adjusted(number: u8, pin: &Pin, direction: i32) -> Result<u16> {
+    let (step, max) = match pin.mode {
+        Some(PinMode::PWM) => {
+            let res = pin.resolution(PinMode::PWM).unwrap_or(8);
+            let max = 1u32
+                .checked_shl(res.into())
+                .map(|n| (n - 1).min(u32::from(u16::MAX)) as i32)
+                .ok_or_else(|| format!("pin {} reports an unusable PWM resolution of {} bits", number, res))?;
+            // Pins with fewer values than steps still move by one.
+            (((max + 1) / PWM_STEPS).max(1), max)
+        }
+        Some(PinMode::Servo) => (SERVO_STEP, 180),
+        _ => return Err(format!("pin {} is not a PWM or servo output", number).into()),
+    };
+    let current = i32::from(pin.written.unwrap_or(0));
+    Ok((current + direction * step).clamp(0, max) as u16)
+}
+
+/// The value to show for a pin, as read or as last written.
+fn value(row: &PinRow) -> String {
+    let value = match row.pin.mode {
+        Some(PinMode::DigitalOutput) | Some(PinMode::DigitalInput) | Some(PinMode::PullUp) => {
+            row.level.map(u16::from)
+        }
+        Some(PinMode::PWM) | Some(PinMode::Servo) => row.pin.written,
+        _ => row.pin.updated.map(|_| row.pin.value),
+    };
+    value.map(|value| value.to_string()).unwrap_or_else(|| "?".into())
+}
+
+/// The recent reported values of a pin, scaled to its resolution.
+fn sparkline(pin: &Pin) -> String {
+    let history = match pin.history {
+        Some(ref history) if !history.is_empty() => history,
+        _ => return String::new(),
+    };
+    let max = pin
+        .mode
+        .and_then(|mode| pin.resolution(mode))
+        .map(|res| 1u32.checked_shl(res.into()).map_or(u32::MAX, |n| n - 1))
+        .unwrap_or(1)
+        .max(1);
+    history
+        .samples()
+        .map(|sample| {
+            let level = u32::from(sample.value).min(max) * (SPARKS.len() as u32 - 1) / max;
+            SPARKS[level as usize]
+        })
+        .collect()
+}
+
+/// Report the inputs of a board that is set up and run the dashboard until it is closed.
+///
+/// The board is read on its own thread, so that drawing and key commands
+/// never wait for a read to time out.
+fn run_on<T: Split + 'static>(mut conn: Connection<T>, source: &str) -> Result<()> {
+    let title = match conn.board().and_then(|board| board.firmware.clone()) {
+        Some(firmware) => format!(" {} v{}.{} on {} ", firmware.name, firmware.major, firmware.minor, source),
+        None => format!(" {} ", source),
+    };
+    report_inputs(&mut conn)?;
+
+    let (mut reader, writer) = conn.split()?;
+    let board = reader.board();
+    thread::spawn(move || reader.run(&Events::new()));
+    let mut dashboard = Dashboard {
+        writer,
+        board,
+        table: TableState::default().with_selected(0),
+        status: "m: mode  space: toggle output  +/-: PWM or servo  r: reporting  q: quit".into(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        loop {
+            let rows = dashboard.rows();
+            terminal.draw(|frame| dashboard.draw(frame, &rows, &title))?;
+            if event::poll(REFRESH)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !dashboard.key(key.code, &rows) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+/// Keep a history of every pin and turn on reporting for every input, as
+/// the board is found.
+fn report_inputs<T: RW>(conn: &mut Connection<T>) -> Result<()> {
+    let (channels, ports) = {
+        let board: &mut Board = conn.board_mut().ok_or(ErrorKind::ConnectionClosed)?;
+        let numbers: Vec<u8> = board.pins.keys().cloned().collect();
+        for &number in &numbers {
+            board.keep_history(number, Limit::Samples(HISTORY_SAMPLES));
+        }
+        let mut channels = Vec::new();
+        let mut ports = Vec::new();
+        for number in numbers {
+            let pin = &board.pins[&number];
+            match (pin.mode, pin.analog_channel) {
+                (Some(PinMode::AnalogInput), Some(channel)) => channels.push(channel),
+                (Some(mode), _) if mode.is_digital_input() && !ports.contains(&(number / 8)) => ports.push(number / 8),
+                _ => {}
+            }
+        }
+        (channels, ports)
+    };
+    for channel in channels {
+        conn.analog_report(channel, true)?;
+    }
+    for port in ports {
+        conn.digital_report(port, true)?;
+    }
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let (target, baud) = parse_args(env::args().skip(1))?;
+    let builder = ConnectionBuilder::new().baud_rate(baud);
+    let connected = common::connect(&target, &builder)?;
+    connected.announce(&target);
+    match connected.link {
+        Link::Serial(conn) => run_on(conn, &connected.source),
+        Link::Tcp(conn) => run_on(conn, &connected.source),
+    }
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("error: {}", e);
+        for e in e.iter().skip(1) {
+            eprintln!("caused by: {}", e);
+        }
+        ::std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firmata::protocol::{FirmataMsg, PinCapability};
+
+    fn pin(mode: PinMode, capabilities: &[(PinMode, u8)]) -> Pin {
+        Pin {
+            mode: Some(mode),
+            capabilities: Some(capabilities.iter().map(|&(mode, res)| PinCapability { mode, res }).collect()),
+            ..Pin::default()
+        }
+    }
+
+    #[test]
+    fn cycles_through_supported_modes() {
+        let modes = [(PinMode::DigitalInput, 1), (PinMode::DigitalOutput, 1), (PinMode::PWM, 8)];
+        assert_eq!(next_mode(&pin(PinMode::DigitalOutput, &modes)), Some(PinMode::PWM));
+        assert_eq!(next_mode(&pin(PinMode::PWM, &modes)), Some(PinMode::DigitalInput));
+        assert_eq!(next_mode(&pin(PinMode::Servo, &modes)), Some(PinMode::DigitalInput));
+        assert_eq!(next_mode(&pin(PinMode::Servo, &[])), None);
+    }
+
+    #[test]
+    fn steps_pwm_and_servo_values() {
+        let mut pwm = pin(PinMode::PWM, &[(PinMode::PWM, 8)]);
+        pwm.written = Some(250);
+        assert_eq!(adjusted(9, &pwm, 1).unwrap(), 255);
+        assert_eq!(adjusted(9, &pwm, -1).unwrap(), 234);
+
+        // Three bits give fewer values than steps, so each press moves by one.
+        let coarse = pin(PinMode::PWM, &[(PinMode::PWM, 3)]);
+        assert_eq!(adjusted(9, &coarse, 1).unwrap(), 1);
+        assert_eq!(adjusted(9, &coarse, -1).unwrap(), 0);
+
+        let mut servo = pin(PinMode::Servo, &[(PinMode::Servo, 14)]);
+        servo.written = Some(175);
+        assert_eq!(adjusted(9, &servo, 1).unwrap(), 180);
+
+        assert!(adjusted(9, &pin(PinMode::PWM, &[(PinMode::PWM, 40)]), 1).is_err());
+        assert!(adjusted(9, &pin(PinMode::DigitalOutput, &[]), 1).is_err());
+    }
+
+    #[test]
+    fn scales_sparklines_to_the_resolution() {
+        let mut board = Board::default();
+        board.record_mode(14, PinMode::AnalogInput);
+        board.keep_history(14, Limit::Samples(HISTORY_SAMPLES));
+        for &value in &[0, 511, 1023, 2000] {
+            board.update(FirmataMsg::AnalogRead { pin: 14, value });
+        }
+        let mut analog = board.pins[&14].clone();
+        analog.capabilities = Some(vec![PinCapability { mode: PinMode::AnalogInput, res: 10 }]);
+        assert_eq!(sparkline(&analog), "▁▄██");
+
+        analog.capabilities = Some(vec![PinCapability { mode: PinMode::AnalogInput, res: 40 }]);
+        assert_eq!(sparkline(&analog), "▁▁▁▁");
+        assert_eq!(sparkline(&Pin::default()), "");
+    }
+}
@@ -0,0 +1,41 @@
+//! Sits between a host program and a Firmata board, printing every message
+//! passed in either direction.
+//!
+//! ```text
+//! firmata-proxy [--pty | --listen ADDR] (--serial PATH [--baud RATE] | --tcp ADDR)
+//! ```
+//!
+//! With `--pty` (the default) the host program opens the printed TTY path
+//! instead of the board's; with `--listen` it connects over TCP, as it would
+//! to EthernetFirmata.
+//!
+//! The proxy is built on `firmata::pty`, so it only works on Linux.
+
+extern crate firmata;
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+#[cfg(target_os = "linux")]
+mod proxy;
+
+use firmata::errors::*;
+
+#[cfg(target_os = "linux")]
+fn run() -> Result<()> {
+    proxy::run()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run() -> Result<()> {
+    Err("firmata-proxy needs pseudo-terminals, which are only supported on Linux".into())
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("error: {}", e);
+        for e in e.iter().skip(1) {
+            eprintln!("caused by: {}", e);
+        }
+        ::std::process::exit(1);
+    }
+}
@@ -0,0 +1,244 @@
+//! The proxy itself, which presents the board to the host on a pty or a
+//! TCP socket.
+
+use firmata::connection::{Connection, ConnectionBuilder, RW};
+use firmata::errors::*;
+use firmata::parser::{Direction, Message};
+use firmata::protocol::HostCommand;
+use firmata::pty::Pty;
+use firmata::sniffer::{Decoded, Decoder};
+use firmata::tcp::TcpPort;
+use libc;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: firmata-proxy [--pty | --listen ADDR] (--serial PATH [--baud RATE] | --tcp ADDR)";
+
+/// How long to wait for the host before checking the board again.
+const POLL_TIMEOUT_MS: libc::c_int = 1;
+
+enum Host {
+    Pty,
+    Listen(String),
+}
+
+enum Board {
+    Serial(String, usize),
+    Tcp(String),
+}
+
+struct Options {
+    host: Host,
+    board: Board,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options> {
+    let mut host = Host::Pty;
+    let (mut serial, mut tcp, mut baud) = (None, None, 57600);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| Error::from(format!("{} needs a value\n{}", arg, USAGE)));
+        match arg.as_str() {
+            "--pty" => host = Host::Pty,
+            "--listen" => host = Host::Listen(value()?),
+            "--serial" => serial = Some(value()?),
+            "--tcp" => tcp = Some(value()?),
+            "--baud" => {
+                baud = value()?.parse().map_err(|_| Error::from(format!("Invalid baud rate\n{}", USAGE)))?;
+            }
+            _ => return Err(format!("Unknown argument {}\n{}", arg, USAGE).into()),
+        }
+    }
+    let board = match (serial, tcp) {
+        (Some(path), None) => Board::Serial(path, baud),
+        (None, Some(addr)) => Board::Tcp(addr),
+        _ => return Err(USAGE.into()),
+    };
+    Ok(Options { host, board })
+}
+
+/// Open the board end of the link, without resetting or querying it.
+fn open_board(board: &Board) -> Result<Box<dyn RW>> {
+    match *board {
+        Board::Serial(ref path, baud) => match ConnectionBuilder::new().baud_rate(baud).open(path)? {
+            Connection::Open { inner, .. } => Ok(Box::new(inner)),
+            Connection::Closed => unreachable!(),
+        },
+        Board::Tcp(ref addr) => Ok(Box::new(TcpPort::connect(addr.as_str(), Duration::from_millis(5))?)),
+    }
+}
+
+/// Open the host end of the link. The pty, if any, is returned so it stays
+/// open as long as the proxy runs.
+fn open_host(host: &Host) -> Result<(File, Option<Pty>)> {
+    match *host {
+        Host::Pty => {
+            let pty = Pty::open()?;
+            println!("Host programs can open {}", pty.path().display());
+            Ok((pty.master().try_clone()?, Some(pty)))
+        }
+        Host::Listen(ref addr) => {
+            let listener = TcpListener::bind(addr.as_str())?;
+            println!("Waiting for a host on {}", listener.local_addr()?);
+            let (stream, peer) = listener.accept()?;
+            println!("Host connected from {}", peer);
+            stream.set_nodelay(true)?;
+            Ok((File::from(OwnedFd::from(stream)), None))
+        }
+    }
+}
+
+struct Printer {
+    start: Instant,
+    color: bool,
+}
+
+impl Printer {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn print(&self, direction: Direction, decoded: &Decoded) {
+        let at = self.start.elapsed();
+        let stamp = format!("{:>4}.{:06}", at.as_secs(), at.subsec_micros());
+        let arrow = match direction {
+            Direction::HostToDevice => "host  >",
+            Direction::DeviceToHost => "board <",
+        };
+        println!("{} {} {}", stamp, arrow, self.describe(decoded));
+    }
+
+    fn describe(&self, decoded: &Decoded) -> String {
+        match *decoded {
+            Decoded::Message(Message::Host(HostCommand::Reset)) => {
+                self.paint("33", "Reset (host is resyncing)")
+            }
+            Decoded::Message(Message::Host(ref cmd)) => format!("{:?}  [{}]", cmd, hex(&cmd.encode())),
+            Decoded::Message(Message::Device(ref msg)) => format!("{:?}  [{}]", msg, hex(&msg.encode())),
+            Decoded::Unparseable(ref bytes) => self.paint(
+                "1;31",
+                &format!("{} unparseable bytes skipped to resync  [{}]", bytes.len(), hex(bytes)),
+            ),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/// Wait briefly for the host to send something, returning false on timeout.
+fn host_ready(host: &File) -> Result<bool> {
+    let mut pollfd = libc::pollfd { fd: host.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    match unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) } {
+        -1 => Err(io::Error::last_os_error().into()),
+        ready => Ok(ready > 0 && pollfd.revents & (libc::POLLIN | libc::POLLHUP) != 0),
+    }
+}
+
+pub fn run() -> Result<()> {
+    let options = parse_args(env::args().skip(1))?;
+    let mut board = open_board(&options.board)?;
+    let (mut host, _pty) = open_host(&options.host)?;
+
+    let printer = Printer { start: Instant::now(), color: unsafe { libc::isatty(1) } == 1 };
+    let mut decoders = [Decoder::new(Direction::HostToDevice), Decoder::new(Direction::DeviceToHost)];
+    let mut buf = [0; 256];
+    loop {
+        if host_ready(&host)? {
+            let len = host.read(&mut buf)?;
+            if len == 0 {
+                println!("Host disconnected");
+                break;
+            }
+            board.write_all(&buf[..len])?;
+            for decoded in decoders[0].feed(&buf[..len]) {
+                printer.print(Direction::HostToDevice, &decoded);
+            }
+        }
+
+        match board.read(&mut buf) {
+            Ok(0) => {
+                println!("Board disconnected");
+                break;
+            }
+            Ok(len) => {
+                host.write_all(&buf[..len])?;
+                for decoded in decoders[1].feed(&buf[..len]) {
+                    printer.print(Direction::DeviceToHost, &decoded);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    for decoder in &mut decoders {
+        if let Some(rest) = decoder.flush() {
+            printer.print(decoder.direction(), &rest);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firmata::protocol::FirmataMsg;
+
+    fn args(line: &str) -> Result<Options> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_host_and_board() {
+        match args("--serial /dev/ttyACM0") {
+            Ok(Options { host: Host::Pty, board: Board::Serial(ref path, 57600) }) if path == "/dev/ttyACM0" => {}
+            _ => panic!("Expected a pty proxy for the serial board"),
+        }
+        match args("--listen 127.0.0.1:3030 --tcp 10.0.0.2:3030") {
+            Ok(Options { host: Host::Listen(ref host), board: Board::Tcp(ref board) })
+                if host == "127.0.0.1:3030" && board == "10.0.0.2:3030" => {}
+            _ => panic!("Expected a TCP proxy for the TCP board"),
+        }
+        match args("--baud 115200 --serial /dev/ttyUSB0") {
+            Ok(Options { board: Board::Serial(_, 115200), .. }) => {}
+            _ => panic!("Expected the baud rate to be kept"),
+        }
+        assert!(args("--pty").is_err());
+        assert!(args("--serial /dev/ttyACM0 --tcp 10.0.0.2:3030").is_err());
+        assert!(args("--serial").is_err());
+        assert!(args("--baud fast --serial /dev/ttyACM0").is_err());
+    }
+
+    #[test]
+    fn describes_decoded_traffic() {
+        let printer = Printer { start: Instant::now(), color: false };
+        let version = FirmataMsg::ProtocolVersion { major: 2, minor: 5 };
+        assert_eq!(
+            printer.describe(&Decoded::Message(Message::Device(version.clone()))),
+            format!("{:?}  [F9 02 05]", version)
+        );
+        assert_eq!(
+            printer.describe(&Decoded::Message(Message::Host(HostCommand::Reset))),
+            "Reset (host is resyncing)"
+        );
+        assert_eq!(
+            printer.describe(&Decoded::Unparseable(vec![0x12, 0x34])),
+            "2 unparseable bytes skipped to resync  [12 34]"
+        );
+
+        let colored = Printer { start: Instant::now(), color: true };
+        assert_eq!(
+            colored.describe(&Decoded::Unparseable(vec![0x12])),
+            "\x1b[1;31m1 unparseable bytes skipped to resync  [12]\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! The commands understood by the `firmata` tool, and how to run them
+//! against a device.
+
+use device::Device;
+use firmata::board::Board;
+use firmata::connection::DEFAULT_QUERY_TIMEOUT;
+use firmata::errors::*;
+use firmata::events::{Event, EventKind, Filter};
+use firmata::protocol::PinMode;
+use std::convert::TryFrom;
+use std::io;
+use std::time::{Duration, Instant};
+
+pub const USAGE: &str = "commands:
+  info                             firmware, protocol and pin capabilities
+  pins                             mode and value of every pin
+  mode <pin> <mode>                set a pin's mode (input, output, analog, pwm, servo, pullup, i2c, ...)
+  write <pin> <0|1>                set a digital output
+  pwm <pin> <value>                set a PWM duty cycle
+  watch <pin>...                   print values of inputs as they change
+  i2c scan                         list the addresses of attached I2C devices
+  i2c read <address> [register] <count>
+  i2c write <address> <byte>...
+
+pins are numbers, or A0, A1, ... for analog channels";
+
+/// How long `i2c scan` waits for each address to answer.
+const I2C_SCAN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A pin as named on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinRef {
+    Pin(u8),
+    /// An analog channel, written as `A0`, `A1`, ...
+    Channel(u8),
+}
+
+impl PinRef {
+    pub fn parse(s: &str) -> Result<PinRef> {
+        match s.strip_prefix('A').or_else(|| s.strip_prefix('a')) {
+            Some(channel) => channel.parse().map(PinRef::Channel),
+            None => s.parse().map(PinRef::Pin),
+        }
+        .map_err(|_| format!("Invalid pin {:?}", s).into())
+    }
+
+    /// The pin number, looking up analog channels in the board's mapping.
+    pub fn resolve(self, board: &Board) -> Result<u8> {
+        match self {
+            PinRef::Pin(pin) => Ok(pin),
+            PinRef::Channel(channel) => board
+                .analog_pin(channel)
+                .ok_or_else(|| format!("The board has no analog channel A{}", channel).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Info,
+    Pins,
+    Mode { pin: PinRef, mode: PinMode },
+    Write { pin: PinRef, level: bool },
+    Pwm { pin: PinRef, value: u16 },
+    Watch(Vec<PinRef>),
+    I2cScan,
+    I2cRead { address: u8, register: Option<u16>, len: u16 },
+    I2cWrite { address: u8, data: Vec<u8> },
+}
+
+impl Command {
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Command> {
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_ref()).collect();
+        let command = match args[..] {
+            ["info"] => Command::Info,
+            ["pins"] => Command::Pins,
+            ["mode", pin, mode] => Command::Mode { pin: PinRef::parse(pin)?, mode: mode.parse::<PinMode>()? },
+            ["write", pin, level] => Command::Write { pin: PinRef::parse(pin)?, level: parse_level(level)? },
+            ["pwm", pin, value] => Command::Pwm { pin: PinRef::parse(pin)?, value: parse_number(value)? },
+            ["watch", ref pins @ ..] if !pins.is_empty() => {
+                Command::Watch(pins.iter().map(|pin| PinRef::parse(pin)).collect::<Result<_>>()?)
+            }
+            ["i2c", "scan"] => Command::I2cScan,
+            ["i2c", "read", address, len] => Command::I2cRead {
+                address: parse_number(address)?,
+                register: None,
+                len: parse_number(len)?,
+            },
+            ["i2c", "read", address, register, len] => Command::I2cRead {
+                address: parse_number(address)?,
+                register: Some(parse_number(register)?),
+                len: parse_number(len)?,
+            },
+            ["i2c", "write", address, ref data @ ..] if !data.is_empty() => Command::I2cWrite {
+                address: parse_number(address)?,
+                data: data.iter().map(|byte| parse_number(byte)).collect::<Result<_>>()?,
+            },
+            _ => return Err(format!("Unknown command {:?}\n{}", args.join(" "), USAGE).into()),
+        };
+        Ok(command)
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+pub fn parse_number<T: TryFrom<u32>>(s: &str) -> Result<T> {
+    let number = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    number
+        .ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("Invalid or out of range number {:?}", s).into())
+}
+
+fn parse_level(s: &str) -> Result<bool> {
+    match s.to_lowercase().as_str() {
+        "1" | "high" | "on" => Ok(true),
+        "0" | "low" | "off" => Ok(false),
+        _ => Err(format!("Invalid level {:?}, expected 0 or 1", s).into()),
+    }
+}
+
+/// Run a command. `watch` runs until the connection fails.
+pub fn run<W: io::Write + Send>(device: &Device<W>, command: &Command) -> Result<()> {
+    let writer = device.writer();
+    match *command {
+        Command::Info => info(&device.board()),
+        Command::Pins => pins(&device.board()),
+        Command::Mode { pin, mode } => {
+            let pin = checked_pin(&device.board(), pin, mode)?;
+            writer.set_pin_mode(pin, mode)
+        }
+        Command::Write { pin, level } => {
+            let pin = ensure_mode(device, pin, PinMode::DigitalOutput)?;
+            writer.digital_write(pin, level)
+        }
+        Command::Pwm { pin, value } => {
+            let pin = ensure_mode(device, pin, PinMode::PWM)?;
+            let res = device.board().pins.get(&pin).and_then(|p| p.resolution(PinMode::PWM));
+            if let Some(res) = res {
+                let max = 1u32
+                    .checked_shl(res.into())
+                    .map(|n| n - 1)
+                    .ok_or_else(|| format!("Pin {} reports an unusable PWM resolution of {} bits", pin, res))?;
+                if u32::from(value) > max {
+                    return Err(format!("PWM value {} is above the maximum of {} for pin {}", value, max, pin).into());
+                }
+            }
+            writer.analog_write(pin, value)
+        }
+        Command::Watch(ref pins) => watch(device, pins),
+        Command::I2cScan => {
+            i2c_enable(device)?;
+            let mut found = 0;
+            for address in 0x08..0x78 {
+                match device.query_i2c_read(address, None, 1, I2C_SCAN_TIMEOUT) {
+                    Ok(ref data) if !data.is_empty() => {
+                        println!("0x{:02x}", address);
+                        found += 1;
+                    }
+                    Ok(_) | Err(Error(ErrorKind::ResponseTimeout, _)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if found == 0 {
+                println!("No I2C devices answered.");
+            }
+            Ok(())
+        }
+        Command::I2cRead { address, register, len } => {
+            i2c_enable(device)?;
+            let data = device.query_i2c_read(address, register, len, DEFAULT_QUERY_TIMEOUT)?;
+            if data.len() < len as usize {
+                warn_short(address, data.len(), len);
+            }
+            println!("{}", hex(&data));
+            Ok(())
+        }
+        Command::I2cWrite { address, ref data } => {
+            i2c_enable(device)?;
+            writer.i2c_write(address, data)
+        }
+    }
+}
+
+/// Resolve a pin and check that it supports `mode`, if its capabilities are known.
+fn checked_pin(board: &Board, pin: PinRef, mode: PinMode) -> Result<u8> {
+    let number = pin.resolve(board)?;
+    match board.pins.get(&number) {
+        Some(p) if p.capabilities.is_some() && !p.supports(mode) => {
+            Err(format!("Pin {} does not support {} mode", number, mode.name()).into())
+        }
+        None if board.pin_count() > 0 => Err(format!("The board has no pin {}", number).into()),
+        _ => Ok(number),
+    }
+}
+
+/// Set a pin to `mode` unless it already is in it.
+fn ensure_mode<W: io::Write + Send>(device: &Device<W>, pin: PinRef, mode: PinMode) -> Result<u8> {
+    let (pin, current) = {
+        let board = device.board();
+        let pin = checked_pin(&board, pin, mode)?;
+        (pin, board.pins.get(&pin).and_then(|p| p.mode))
+    };
+    if current != Some(mode) {
+        device.writer().set_pin_mode(pin, mode)?;
+    }
+    Ok(pin)
+}
+
+fn i2c_enable<W: io::Write + Send>(device: &Device<W>) -> Result<()> {
+    let pins: Vec<u8> = device
+        .board()
+        .pins
+        .iter()
+        .filter(|&(_, pin)| pin.supports(PinMode::I2C) && pin.mode != Some(PinMode::I2C))
+        .map(|(&number, _)| number)
+        .collect();
+    for pin in pins {
+        device.writer().set_pin_mode(pin, PinMode::I2C)?;
+    }
+    device.writer().i2c_config(0)
+}
+
+fn warn_short(address: u8, got: usize, len: u16) {
+    eprintln!("warning: device 0x{:02x} sent {} of {} bytes", address, got, len);
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn sorted_pins(board: &Board) -> Vec<u8> {
+    let mut pins: Vec<u8> = board.pins.keys().cloned().collect();
+    pins.sort();
+    pins
+}
+
+/// The name of a pin, with its analog channel if it has one.
+pub fn pin_label(board: &Board, pin: u8) -> String {
+    match board.pins.get(&pin).and_then(|p| p.analog_channel) {
+        Some(channel) => format!("{} (A{})", pin, channel),
+        None => pin.to_string(),
+    }
+}
+
+fn info(board: &Board) -> Result<()> {
+    match board.firmware {
+        Some(ref firmware) => println!("firmware: {} v{}.{}", firmware.name, firmware.major, firmware.minor),
+        None => println!("firmware: unknown"),
+    }
+    match board.protocol {
+        Some(ref protocol) => println!("protocol: v{}.{}", protocol.0, protocol.1),
+        None => println!("protocol: unknown"),
+    }
+    println!("pins: {} ({} analog)", board.pin_count(), board.analog_channels().len());
+    for pin in sorted_pins(board) {
+        let modes = match board.pins[&pin].capabilities {
+            Some(ref modes) if !modes.is_empty() => modes
+                .iter()
+                .map(|cap| format!("{}/{}", cap.mode.name(), cap.res))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Some(_) => "(reserved)".into(),
+            None => "(unknown)".into(),
+        };
+        println!("  {:<8} {}", pin_label(board, pin), modes);
+    }
+    Ok(())
+}
+
+fn pins(board: &Board) -> Result<()> {
+    println!("  {:<8} {:<8} {:>6}", "pin", "mode", "value");
+    for pin in sorted_pins(board) {
+        let p = &board.pins[&pin];
+        if p.capabilities.as_ref().is_some_and(|modes| modes.is_empty()) {
+            continue;
+        }
+        let mode = p.mode.map(PinMode::name).unwrap_or_else(|| "?".into());
+        let value = match p.mode {
+            Some(PinMode::DigitalOutput) | Some(PinMode::DigitalInput) | Some(PinMode::PullUp) => board
+                .digital_read(pin)
+                .map(|level| (level as u8).to_string())
+                .unwrap_or_else(|| "?".into()),
+            Some(PinMode::PWM) | Some(PinMode::Servo) => {
+                p.written.map(|value| value.to_string()).unwrap_or_else(|| "?".into())
+            }
+            _ if p.updated.is_some() => p.value.to_string(),
+            _ => "?".into(),
+        };
+        println!("  {:<8} {:<8} {:>6}", pin_label(board, pin), mode, value);
+    }
+    Ok(())
+}
+
+/// Turn on reporting for each pin, returning their numbers. Pins that
+/// aren't inputs are made digital inputs.
+pub fn report<W: io::Write + Send>(device: &Device<W>, pins: &[PinRef]) -> Result<Vec<u8>> {
+    let writer = device.writer();
+    let mut numbers = Vec::new();
+    for &pin in pins {
+        let (number, mode, channel) = {
+            let board = device.board();
+            let number = pin.resolve(&board)?;
+            let p = board.pins.get(&number);
+            (number, p.and_then(|p| p.mode), p.and_then(|p| p.analog_channel))
+        };
+        match (mode, channel) {
+            (Some(PinMode::AnalogInput), Some(channel)) => writer.analog_report(channel, true)?,
+            (Some(mode), _) if mode.is_digital_input() => writer.digital_report(number / 8, true)?,
+            _ => {
+                let number = checked_pin(&device.board(), pin, PinMode::DigitalInput)?;
+                eprintln!("Setting pin {} to input to watch it", number);
+                writer.set_pin_mode(number, PinMode::DigitalInput)?;
+                writer.digital_report(number / 8, true)?;
+            }
+        }
+        numbers.push(number);
+    }
+    Ok(numbers)
+}
+
+/// Turn reporting off for each pin.
+pub fn unreport<W: io::Write + Send>(device: &Device<W>, pins: &[PinRef]) -> Result<()> {
+    for &pin in pins {
+        let (number, reported_channel) = {
+            let board = device.board();
+            let number = pin.resolve(&board)?;
+            let channel = board.pins.get(&number).and_then(|p| p.analog_channel);
+            (number, channel.filter(|channel| board.analog_reports.contains(channel)))
+        };
+        match reported_channel {
+            Some(channel) => device.writer().analog_report(channel, false)?,
+            None => device.writer().digital_report(number / 8, false)?,
+        }
+    }
+    Ok(())
+}
+
+/// Watch pins, printing every change until the connection fails.
+fn watch<W: io::Write + Send>(device: &Device<W>, pins: &[PinRef]) -> Result<()> {
+    let events = device.subscribe(Filter::new().kind(EventKind::PinChanged).kind(EventKind::Disconnected));
+    let watched = report(device, pins)?;
+
+    let start = Instant::now();
+    for event in events {
+        match event {
+            Event::PinChanged { pin, value } if watched.contains(&pin) => {
+                let at = start.elapsed();
+                let label = pin_label(&device.board(), pin);
+                println!("{:>4}.{:03} {:<8} {}", at.as_secs(), at.subsec_millis(), label, value);
+            }
+            Event::Disconnected => break,
+            _ => {}
+        }
+    }
+    Err(ErrorKind::ConnectionClosed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            Command::parse(&["mode", "A2", "pullup"]).unwrap(),
+            Command::Mode { pin: PinRef::Channel(2), mode: PinMode::PullUp }
+        );
+        assert_eq!(
+            Command::parse(&["write", "13", "high"]).unwrap(),
+            Command::Write { pin: PinRef::Pin(13), level: true }
+        );
+        assert_eq!(
+            Command::parse(&["watch", "2", "a0"]).unwrap(),
+            Command::Watch(vec![PinRef::Pin(2), PinRef::Channel(0)])
+        );
+        assert_eq!(
+            Command::parse(&["i2c", "read", "0x68", "0x3b", "6"]).unwrap(),
+            Command::I2cRead { address: 0x68, register: Some(0x3B), len: 6 }
+        );
+        assert_eq!(
+            Command::parse(&["i2c", "write", "0x68", "0x6b", "0"]).unwrap(),
+            Command::I2cWrite { address: 0x68, data: vec![0x6B, 0] }
+        );
+        assert!(Command::parse(&["pwm", "9", "70000"]).is_err());
+        assert!(Command::parse(&["watch"]).is_err());
+        assert!(Command::parse(&["mode", "3", "turbo"]).is_err());
+    }
+}
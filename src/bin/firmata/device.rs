@@ -0,0 +1,109 @@
+//! A board whose messages are read on a background thread, so that
+//! commands are written straight away instead of waiting for a read to
+//! time out.
+
+use firmata::board::Board;
+use firmata::connection::Connection;
+use firmata::errors::*;
+use firmata::events::{Event, EventKind, Events, Filter};
+use firmata::split::{Split, Writer};
+use std::io;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Device<W>
+where
+    W: io::Write + Send
+{
+    writer: Writer<W>,
+    board: Arc<Mutex<Board>>,
+    events: Events,
+}
+
+impl<W> Device<W>
+where
+    W: io::Write + Send
+{
+    /// Split `conn` and apply everything the board sends on a new thread,
+    /// which runs until the board goes away.
+    pub fn start<T>(conn: Connection<T>) -> Result<Self>
+    where
+        T: Split<Writer = W> + 'static
+    {
+        let (mut reader, writer) = conn.split()?;
+        let board = reader.board();
+        let events = Events::new();
+        let published = events.clone();
+        thread::spawn(move || reader.run(&published));
+        Ok(Device { writer, board, events })
+    }
+
+    /// Send commands to the board.
+    pub fn writer(&self) -> &Writer<W> {
+        &self.writer
+    }
+
+    /// Look at the board. The reader waits while the lock is held, so
+    /// don't keep it for longer than it takes to look.
+    pub fn board(&self) -> MutexGuard<'_, Board> {
+        self.board.lock().unwrap()
+    }
+
+    /// Receive the events matching `filter` as the reader sees them.
+    pub fn subscribe(&self, filter: Filter) -> Receiver<Event> {
+        self.events.subscribe(filter)
+    }
+
+    /// Read from an I2C device and wait for the reply, as
+    /// `Connection::query_i2c_read` does.
+    pub fn query_i2c_read(&self, address: u8, register: Option<u16>, len: u16, timeout: Duration) -> Result<Vec<u8>> {
+        let replies = self.subscribe(Filter::new().kind(EventKind::I2cReply).kind(EventKind::Disconnected));
+        self.writer.i2c_read(address, register, len)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match replies.recv_timeout(wait) {
+                Ok(Event::I2cReply { address: from, data, .. }) if from == u16::from(address) => return Ok(data),
+                Ok(Event::Disconnected) | Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ErrorKind::ConnectionClosed.into())
+                }
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::ResponseTimeout.into()),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use firmata::connection::ConnectionBuilder;
+    use firmata::emulator::Emulator;
+    use firmata::protocol::PinMode;
+    use firmata::pty::VirtualBoard;
+
+    #[test]
+    fn writes_while_the_reader_applies_reports() {
+        let board = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let conn = ConnectionBuilder::new().open(board.path().to_str().unwrap()).unwrap();
+        let device = Device::start(conn).unwrap();
+        let changes = device.subscribe(Filter::new().pin(4));
+
+        device.writer().set_pin_mode(4, PinMode::DigitalInput).unwrap();
+        device.writer().digital_report(0, true).unwrap();
+        let emulator = board.device();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !emulator.is_digital_reporting(0) {
+            assert!(Instant::now() < deadline, "reporting was not enabled");
+            thread::sleep(Duration::from_millis(5));
+        }
+        emulator.set_digital(4, true);
+
+        // Enabling reporting may report the port as it was first.
+        let high = Event::PinChanged { pin: 4, value: 1 };
+        while changes.recv_timeout(Duration::from_secs(1)).unwrap() != high {}
+        assert_eq!(device.board().pins[&4].value, 1);
+    }
+}
@@ -0,0 +1,125 @@
+//! Inspect and control a Firmata board from the command line.
+//!
+//! ```text
+//! firmata [--port PATH [--baud RATE] | --tcp ADDR] <command>
+//! firmata [--port PATH [--baud RATE] | --tcp ADDR] repl [--record SCRIPT]
+//! firmata [--port PATH [--baud RATE] | --tcp ADDR] run SCRIPT
+//! ```
+//!
+//! Without `--port` or `--tcp` the first board found by
+//! `discovery::discover` is used. The REPL needs the `repl` feature.
+
+extern crate firmata;
+#[cfg(feature = "repl")]
+extern crate rustyline;
+extern crate serial_unix;
+
+mod commands;
+#[path = "../common/mod.rs"]
+mod common;
+mod device;
+#[cfg(feature = "repl")]
+mod repl;
+mod shell;
+
+use commands::Command;
+use common::{Link, Target};
+use device::Device;
+use shell::Shell;
+use firmata::connection::{Connection, ConnectionBuilder};
+use firmata::split::Split;
+use firmata::errors::*;
+use std::env;
+use std::fs::File;
+use std::io;
+
+const USAGE: &str = "usage: firmata [--port PATH [--baud RATE] | --tcp ADDR] <command>
+       firmata [--port PATH [--baud RATE] | --tcp ADDR] repl [--record SCRIPT]
+       firmata [--port PATH [--baud RATE] | --tcp ADDR] run SCRIPT";
+
+enum Mode {
+    Once(Command),
+    Repl { record: Option<String> },
+    Script(String),
+}
+
+struct Options {
+    target: Target,
+    baud: usize,
+    mode: Mode,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options> {
+    let usage = || Error::from(format!("{}\n\n{}", USAGE, commands::USAGE));
+    let (mut target, mut baud) = (Target::Discover, 57600);
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        let value = args.next().ok_or_else(usage)?;
+        match arg.as_str() {
+            "--port" => target = Target::Serial(value),
+            "--tcp" => target = Target::Tcp(value),
+            "--baud" => baud = commands::parse_number(&value)?,
+            _ => return Err(usage()),
+        }
+    }
+    let command: Vec<String> = args.collect();
+    let mode = match command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => return Err(usage()),
+        ["repl"] => Mode::Repl { record: None },
+        ["repl", "--record", script] => Mode::Repl { record: Some(script.into()) },
+        ["run", script] => Mode::Script(script.into()),
+        _ => Mode::Once(Command::parse(&command)?),
+    };
+    Ok(Options { target, baud, mode })
+}
+
+/// Run a command, a script or the REPL against a board that is set up.
+fn run_on<T: Split + 'static>(conn: Connection<T>, mode: &Mode) -> Result<()> {
+    let device = Device::start(conn)?;
+    match *mode {
+        Mode::Once(ref command) => commands::run(&device, command),
+        Mode::Repl { ref record } => {
+            let mut shell = Shell::new(device);
+            if let Some(ref script) = *record {
+                shell.record_to(File::create(script)?);
+            }
+            repl(shell)
+        }
+        Mode::Script(ref script) => {
+            let mut shell = Shell::new(device);
+            shell.print_updates(|line| println!("{}", line));
+            shell.run_script(script)
+        }
+    }
+}
+
+#[cfg(feature = "repl")]
+fn repl<W: io::Write + Send + 'static>(shell: Shell<W>) -> Result<()> {
+    repl::run(shell)
+}
+
+#[cfg(not(feature = "repl"))]
+fn repl<W: io::Write + Send + 'static>(_: Shell<W>) -> Result<()> {
+    Err("This build of firmata has no REPL; rebuild it with --features repl".into())
+}
+
+fn run() -> Result<()> {
+    let options = parse_args(env::args().skip(1).collect())?;
+    let builder = ConnectionBuilder::new().baud_rate(options.baud);
+    let connected = common::connect(&options.target, &builder)?;
+    connected.announce(&options.target);
+    match connected.link {
+        Link::Serial(conn) => run_on(conn, &options.mode),
+        Link::Tcp(conn) => run_on(conn, &options.mode),
+    }
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("error: {}", e);
+        for e in e.iter().skip(1) {
+            eprintln!("caused by: {}", e);
+        }
+        ::std::process::exit(1);
+    }
+}
@@ -0,0 +1,172 @@
+//! An interactive shell with line editing and completion of pins and modes.
+
+use firmata::board::Board;
+use firmata::errors::*;
+use firmata::protocol::PinMode;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, ExternalPrinter, Helper};
+use shell::{Flow, Shell};
+use std::io;
+
+const PROMPT: &str = "firmata> ";
+
+const COMMANDS: &[&str] = &[
+    "info", "pins", "mode", "write", "pwm", "watch", "unwatch", "i2c", "sleep", "help", "quit",
+];
+
+/// What completion knows about a pin.
+struct PinInfo {
+    names: Vec<String>,
+    modes: Vec<PinMode>,
+}
+
+/// Completes commands, and pins and modes from the board's capabilities.
+struct Completion {
+    pins: Vec<PinInfo>,
+}
+
+impl Completion {
+    fn new(board: &Board) -> Self {
+        let mut numbers: Vec<u8> = board.pins.keys().cloned().collect();
+        numbers.sort();
+        let pins = numbers
+            .into_iter()
+            .filter_map(|number| {
+                let pin = &board.pins[&number];
+                let modes: Vec<PinMode> = pin.capabilities.as_ref()?.iter().map(|cap| cap.mode).collect();
+                if modes.is_empty() {
+                    return None;
+                }
+                let mut names = vec![number.to_string()];
+                names.extend(pin.analog_channel.map(|channel| format!("A{}", channel)));
+                Some(PinInfo { names, modes })
+            })
+            .collect();
+        Completion { pins }
+    }
+
+    /// The names of the pins that support `mode`, or of every pin.
+    fn pins(&self, mode: Option<PinMode>) -> Vec<String> {
+        self.pins
+            .iter()
+            .filter(|pin| mode.is_none_or(|mode| pin.modes.contains(&mode)))
+            .flat_map(|pin| pin.names.iter().cloned())
+            .collect()
+    }
+
+    fn modes(&self, name: &str) -> Vec<String> {
+        self.pins
+            .iter()
+            .find(|pin| pin.names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+            .map(|pin| pin.modes.iter().map(|mode| mode.name()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The words that can follow `args`.
+    fn candidates(&self, args: &[&str]) -> Vec<String> {
+        match *args {
+            [] => COMMANDS.iter().map(|&command| command.into()).collect(),
+            ["i2c"] => vec!["scan".into(), "read".into(), "write".into()],
+            ["mode"] => self.pins(None),
+            ["mode", pin] => self.modes(pin),
+            ["write"] => self.pins(Some(PinMode::DigitalOutput)),
+            ["pwm"] => self.pins(Some(PinMode::PWM)),
+            ["watch", ..] | ["unwatch", ..] => self.pins(None),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before.rfind(' ').map_or(0, |space| space + 1);
+        let args: Vec<&str> = before[..start].split_whitespace().collect();
+        let word = &before[start..];
+        let mut candidates: Vec<String> = self
+            .candidates(&args)
+            .into_iter()
+            .filter(|candidate| candidate.to_lowercase().starts_with(&word.to_lowercase()))
+            .collect();
+        // A single match is complete, so move on to the next word.
+        if candidates.len() == 1 {
+            candidates[0].push(' ');
+        }
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// Read and run commands until the user quits.
+pub fn run<W: io::Write + Send + 'static>(mut shell: Shell<W>) -> Result<()> {
+    let readline_error = |e: ReadlineError| Error::from(format!("Terminal error: {}", e));
+
+    let mut editor = Editor::<Completion, DefaultHistory>::new().map_err(readline_error)?;
+    let completion = shell.with_board(Completion::new);
+    editor.set_helper(Some(completion));
+
+    // Updates are printed above the prompt without disturbing the line
+    // being edited.
+    let mut printer = editor.create_external_printer().map_err(readline_error)?;
+    shell.print_updates(move |line| {
+        let _ = printer.print(line);
+    });
+
+    println!("Type help for a list of commands.");
+    loop {
+        match editor.readline(PROMPT) {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                match shell.execute(&line) {
+                    Ok(Flow::Continue) => {}
+                    Ok(Flow::Quit) => break,
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => {}
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firmata::protocol::FirmataMsg;
+    use firmata::emulator::Profile;
+
+    #[test]
+    fn completes_pins_and_modes() {
+        let profile = Profile::uno();
+        let mut board = Board::default();
+        board.update(FirmataMsg::CapabilityResponse(profile.capabilities));
+        board.update(FirmataMsg::AnalogMappingResponse(profile.analog_mapping));
+        let completion = Completion::new(&board);
+
+        assert_eq!(completion.candidates(&["pwm"]), ["3", "5", "6", "9", "10", "11"]);
+        assert!(completion.candidates(&["watch", "2"]).contains(&"A0".to_string()));
+        assert_eq!(completion.candidates(&["mode", "a5"]), ["input", "output", "pullup", "analog", "i2c"]);
+
+        let history = DefaultHistory::new();
+        let (start, candidates) = completion.complete("mode 9 p", 8, &Context::new(&history)).unwrap();
+        assert_eq!((start, candidates), (7, vec!["pullup".to_string(), "pwm".to_string()]));
+    }
+}
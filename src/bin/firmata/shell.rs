@@ -0,0 +1,136 @@
+//! Running commands against a connection kept open between them, for the
+//! REPL and for replaying scripts.
+//!
+//! A script is a file of commands, one per line, as typed at the REPL.
+//! Blank lines and lines starting with `#` are ignored.
+
+use commands::{self, Command, PinRef};
+use device::Device;
+use firmata::errors::*;
+use firmata::events::{Event, EventKind, Filter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const USAGE: &str = "shell commands:
+  sleep <ms>                       pause, for scripts
+  unwatch <pin>...                 stop reporting pins
+  help                             show this list
+  quit";
+
+/// What the shell should do after a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub struct Shell<W>
+where
+    W: io::Write + Send
+{
+    device: Arc<Device<W>>,
+    record: Option<File>,
+}
+
+impl<W> Shell<W>
+where
+    W: io::Write + Send + 'static
+{
+    /// Run commands against `device`, whose reader keeps its board up to date.
+    pub fn new(device: Device<W>) -> Self {
+        Shell { device: Arc::new(device), record: None }
+    }
+
+    /// Append every command that runs successfully to `file`, so the
+    /// session can be replayed with `firmata run`.
+    pub fn record_to(&mut self, file: File) {
+        self.record = Some(file);
+    }
+
+    /// Pass a description of every change of a reported pin, and every
+    /// message from the device, to `print` on a background thread.
+    pub fn print_updates<P>(&self, mut print: P)
+    where
+        P: FnMut(String) + Send + 'static
+    {
+        let filter = Filter::new().kind(EventKind::PinChanged).kind(EventKind::StringData);
+        let updates = self.device.subscribe(filter);
+        let device = Arc::downgrade(&self.device);
+        thread::spawn(move || {
+            for event in updates {
+                let device = match device.upgrade() {
+                    Some(device) => device,
+                    None => break,
+                };
+                let line = match event {
+                    Event::PinChanged { pin, value } => {
+                        let label = commands::pin_label(&device.board(), pin);
+                        format!("pin {} = {}", label, value)
+                    }
+                    Event::StringData(ref string) => format!("device: {}", string),
+                    _ => continue,
+                };
+                print(line);
+            }
+        });
+    }
+
+    /// Look at the board, holding it for as long as `f` runs.
+    #[cfg(feature = "repl")]
+    pub fn with_board<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&::firmata::board::Board) -> R
+    {
+        f(&self.device.board())
+    }
+
+    /// Run a line of input.
+    pub fn execute(&mut self, line: &str) -> Result<Flow> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args[..] {
+            [] => return Ok(Flow::Continue),
+            [word, ..] if word.starts_with('#') => return Ok(Flow::Continue),
+            ["quit"] | ["exit"] => return Ok(Flow::Quit),
+            ["help"] => {
+                println!("{}\n\n{}", commands::USAGE, USAGE);
+                return Ok(Flow::Continue);
+            }
+            ["sleep", ms] => thread::sleep(Duration::from_millis(commands::parse_number::<u32>(ms)?.into())),
+            ["unwatch", ref pins @ ..] if !pins.is_empty() => {
+                let pins = pins.iter().map(|pin| PinRef::parse(pin)).collect::<Result<Vec<_>>>()?;
+                commands::unreport(&self.device, &pins)?;
+            }
+            _ => {
+                match Command::parse(&args)? {
+                    // The updates are printed as they arrive, so watching
+                    // only turns reporting on.
+                    Command::Watch(ref pins) => {
+                        commands::report(&self.device, pins)?;
+                    }
+                    ref command => commands::run(&self.device, command)?,
+                }
+            }
+        }
+        if let Some(ref mut record) = self.record {
+            writeln!(record, "{}", args.join(" "))?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Run every line of a script, stopping at the first that fails.
+    pub fn run_script(&mut self, path: &str) -> Result<()> {
+        let script = BufReader::new(File::open(path)?);
+        for (number, line) in script.lines().enumerate() {
+            let line = line?;
+            match self.execute(&line) {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Quit) => break,
+                Err(e) => return Err(format!("{}:{}: {}", path, number + 1, e).into()),
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,73 @@
-use protocol::FirmataMsg;
-use std::collections::HashMap;
+use edges::{Detector, Trigger};
+use events::Event;
+use history::{History, Limit};
+use protocol::{FirmataMsg, PinCapability, PinMode};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Pin {
+    /// The last value reported by the device.
     pub value: u16,
+    /// The mode set by the host or reported by the device.
+    pub mode: Option<PinMode>,
+    /// The modes the pin supports, once the capability response arrived.
+    /// A pin that is reported with no modes is unavailable.
+    pub capabilities: Option<Vec<PinCapability>>,
+    /// The analog channel that reports this pin, if it has one.
+    pub analog_channel: Option<u8>,
+    /// Whether the host enabled reporting for the pin's analog channel or
+    /// digital port.
+    pub reporting: bool,
+    /// When the device last reported the pin.
+    pub updated: Option<Instant>,
+    /// The last value the host wrote to the pin.
+    pub written: Option<u16>,
+    /// Past values reported by the device, if `Board::keep_history` enabled it.
+    pub history: Option<History>,
 }
 
-#[derive(Debug, Default)]
+impl Pin {
+    /// The resolution of the pin in `mode`, if it supports that mode.
+    pub fn resolution(&self, mode: PinMode) -> Option<u8> {
+        self.capabilities
+            .as_ref()
+            .and_then(|caps| caps.iter().find(|cap| cap.mode == mode))
+            .map(|cap| cap.res)
+    }
+
+    /// Whether the pin supports `mode`.
+    pub fn supports(&self, mode: PinMode) -> bool {
+        self.resolution(mode).is_some()
+    }
+
+    /// Set the pin to a value reported by the device at `at`.
+    fn report(&mut self, value: u16, at: Instant) {
+        self.value = value;
+        self.updated = Some(at);
+        if let Some(ref mut history) = self.history {
+            history.push(value, at);
+        }
+    }
+}
+
+/// The 8 bit state of a digital port, least significant pin first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PortState {
+    /// The last value the host wrote to the port.
+    pub written: Option<u8>,
+    /// The last levels the device reported for the port.
+    pub reported: Option<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Firmware {
     pub major: u8,
     pub minor: u8,
     pub name: String,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Protocol(pub u8, pub u8);
 
 #[derive(Debug, Default)]
@@ -21,6 +75,18 @@ pub struct Board {
     pub firmware: Option<Firmware>,
     pub protocol: Option<Protocol>,
     pub pins: HashMap<u8, Pin>,
+    /// Pin modes set by the host, replayed when a connection is reestablished.
+    pub modes: HashMap<u8, PinMode>,
+    /// Analog channels the host enabled reporting for.
+    pub analog_reports: HashSet<u8>,
+    /// Digital ports the host enabled reporting for.
+    pub digital_reports: HashSet<u8>,
+    /// The analog channel of each pin, indexed by pin number.
+    pub analog_mapping: Vec<Option<u8>>,
+    /// The state of each digital port, indexed by port number.
+    pub ports: HashMap<u8, PortState>,
+    /// Edge detectors of the watched digital inputs.
+    edges: HashMap<u8, Detector>,
 }
 
 impl Board {
@@ -28,11 +94,222 @@ impl Board {
         *self = Board::default();
     }
 
-    pub fn update(&mut self, msg: FirmataMsg) {
+    /// The number of pins on the board, known once capabilities are reported.
+    pub fn pin_count(&self) -> usize {
+        self.pins
+            .iter()
+            .filter(|&(_, pin)| pin.capabilities.is_some())
+            .map(|(&number, _)| number as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The number of 8 pin digital ports on the board.
+    pub fn port_count(&self) -> usize {
+        self.pin_count().div_ceil(8)
+    }
+
+    /// The pins that have an analog channel, as (channel, pin) pairs.
+    pub fn analog_channels(&self) -> Vec<(u8, u8)> {
+        self.analog_mapping
+            .iter()
+            .enumerate()
+            .filter_map(|(pin, channel)| channel.map(|channel| (channel, pin as u8)))
+            .collect()
+    }
+
+    /// The pin number of an analog channel.
+    pub fn analog_pin(&self, channel: u8) -> Option<u8> {
+        self.analog_mapping
+            .iter()
+            .position(|&c| c == Some(channel))
+            .map(|pin| pin as u8)
+    }
+
+    /// The state of a digital port, if it has been written or reported.
+    pub fn port_state(&self, port: u8) -> Option<PortState> {
+        self.ports.get(&port).cloned()
+    }
+
+    /// The current level of a digital pin.
+    ///
+    /// Output pins read back the level the host last wrote; every other pin
+    /// reads the level last reported by the device for its port.
+    pub fn digital_read(&self, pin: u8) -> Option<bool> {
+        let (port, bit) = (pin / 8, pin % 8);
+        let state = self.ports.get(&port)?;
+        let is_output = self.pins.get(&pin).and_then(|pin| pin.mode) == Some(PinMode::DigitalOutput);
+        let value = if is_output { state.written } else { state.reported };
+        value.map(|value| value >> bit & 1 == 1)
+    }
+
+    /// Whether the device understands the single pin digital write added in
+    /// protocol version 2.5.
+    pub fn supports_pin_write(&self) -> bool {
+        match self.protocol {
+            Some(Protocol(major, minor)) => (major, minor) >= (2, 5),
+            None => false,
+        }
+    }
+
+    /// The value to write to a pin's port to set only that pin to `level`,
+    /// keeping the other pins at the levels last written by the host.
+    pub fn port_value_with(&self, pin: u8, level: bool) -> u8 {
+        let (port, bit) = (pin / 8, pin % 8);
+        let written = self.ports.get(&port).and_then(|state| state.written).unwrap_or(0);
+        if level {
+            written | 1 << bit
+        } else {
+            written & !(1 << bit)
+        }
+    }
+
+    /// Report `Event::Edge` when a digital input changes level in the way
+    /// `trigger` asks for, ignoring changes within `debounce` of the last edge.
+    ///
+    /// Edges are only seen while digital reporting is enabled for the pin's port.
+    /// A change that settles within the debounce window is reported by
+    /// `poll_edges` once the window ends; the `Worker` calls it after every
+    /// update.
+    pub fn watch_edges(&mut self, pin: u8, trigger: Trigger, debounce: Option<Duration>) {
+        self.pins.entry(pin).or_default();
+        self.edges.insert(pin, Detector::new(trigger, debounce));
+    }
+
+    /// Keep the values reported for a pin, bounded by `limit`.
+    pub fn keep_history(&mut self, pin: u8, limit: Limit) {
+        self.pins.entry(pin).or_default().history = Some(History::new(limit));
+    }
+
+    /// Stop keeping the values reported for a pin.
+    pub fn drop_history(&mut self, pin: u8) {
+        if let Some(pin) = self.pins.get_mut(&pin) {
+            pin.history = None;
+        }
+    }
+
+    /// Report the edges of watched pins whose level settled within their
+    /// debounce window, which has ended by `now`.
+    pub fn poll_edges(&mut self, now: Instant) -> Vec<Event> {
+        self.edges
+            .iter_mut()
+            .filter_map(|(&pin, detector)| detector.poll(now).map(|edge| Event::Edge { pin, edge, at: now }))
+            .collect()
+    }
+
+    /// Stop reporting the edges of a pin.
+    pub fn unwatch_edges(&mut self, pin: u8) {
+        self.edges.remove(&pin);
+    }
+
+    /// The pin an analog channel reports, which is the channel number itself
+    /// until the analog mapping is known.
+    fn channel_pin(&self, channel: u8) -> u8 {
+        if self.analog_mapping.is_empty() {
+            channel
+        } else {
+            self.analog_pin(channel).unwrap_or(channel)
+        }
+    }
+
+    /// Record a pin mode set by the host.
+    pub fn record_mode(&mut self, pin: u8, mode: PinMode) {
+        self.modes.insert(pin, mode);
+        self.pins.entry(pin).or_default().mode = Some(mode);
+    }
+
+    /// Record the host enabling or disabling reporting of an analog channel.
+    pub fn record_analog_report(&mut self, channel: u8, state: bool) {
+        if state {
+            self.analog_reports.insert(channel);
+        } else {
+            self.analog_reports.remove(&channel);
+        }
+        let pin = self.channel_pin(channel);
+        self.pins.entry(pin).or_default().reporting = state;
+    }
+
+    /// Record the host enabling or disabling reporting of a digital port.
+    pub fn record_digital_report(&mut self, port: u8, state: bool) {
+        if state {
+            self.digital_reports.insert(port);
+        } else {
+            self.digital_reports.remove(&port);
+        }
+        for pin in port_pins(port) {
+            if let Some(pin) = self.pins.get_mut(&pin) {
+                if pin.analog_channel.is_none() {
+                    pin.reporting = state;
+                }
+            }
+        }
+    }
+
+    /// Record a value the host wrote to a whole digital port.
+    pub fn record_port_write(&mut self, port: u8, value: u8) {
+        self.ports.entry(port).or_default().written = Some(value);
+        for (bit, pin) in port_pins(port).enumerate() {
+            self.pins.entry(pin).or_default().written = Some(u16::from(value >> bit & 1));
+        }
+    }
+
+    /// Record a level the host wrote to a single digital pin.
+    pub fn record_pin_write(&mut self, pin: u8, level: bool) {
+        let value = self.port_value_with(pin, level);
+        self.ports.entry(pin / 8).or_default().written = Some(value);
+        self.pins.entry(pin).or_default().written = Some(u16::from(level));
+    }
+
+    /// Record a PWM or servo value the host wrote to a pin.
+    pub fn record_analog_write(&mut self, pin: u8, value: u16) {
+        self.pins.entry(pin).or_default().written = Some(value);
+    }
+
+    /// Apply a message from the device, returning the events it caused.
+    pub fn update(&mut self, msg: FirmataMsg) -> Vec<Event> {
+        self.update_at(msg, Instant::now())
+    }
+
+    /// Apply a message from the device that arrived at `now`.
+    pub fn update_at(&mut self, msg: FirmataMsg, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
         match msg {
-            FirmataMsg::AnalogRead { pin, value } => {
-                let pin_value = Pin { value: value };
-                self.pins.insert(pin, pin_value);
+            FirmataMsg::AnalogRead { pin: channel, value } => {
+                let number = self.channel_pin(channel);
+                let pin = self.pins.entry(number).or_default();
+                if pin.updated.is_none() || pin.value != value {
+                    events.push(Event::PinChanged { pin: number, value });
+                }
+                pin.report(value, now);
+            }
+            FirmataMsg::DigitalRead { port, value } => {
+                self.ports.entry(port).or_default().reported = Some(value);
+                let (count, reporting) = (self.pin_count(), self.digital_reports.contains(&port));
+                for (bit, number) in port_pins(port).enumerate() {
+                    // The last port can be reported beyond the pins the board has.
+                    if count > 0 && usize::from(number) >= count {
+                        break;
+                    }
+                    let pin = self.pins.entry(number).or_insert_with(|| Pin { reporting, ..Pin::default() });
+                    // The levels of pins that aren't digital inputs are meaningless.
+                    let is_input = match pin.mode {
+                        Some(mode) => mode.is_digital_input(),
+                        None => pin.analog_channel.is_none(),
+                    };
+                    if !is_input {
+                        continue;
+                    }
+                    let level = u16::from(value >> bit & 1);
+                    if pin.updated.is_none() || pin.value != level {
+                        events.push(Event::PinChanged { pin: number, value: level });
+                    }
+                    pin.report(level, now);
+                    if let Some(detector) = self.edges.get_mut(&number) {
+                        if let Some(edge) = detector.feed(level == 1, now) {
+                            events.push(Event::Edge { pin: number, edge, at: now });
+                        }
+                    }
+                }
             }
             FirmataMsg::QueryFirmware {
                 major,
@@ -44,18 +321,286 @@ impl Board {
                     minor: minor,
                     name: String::from_utf8_lossy(&firmware_name).into(),
                 };
+                events.push(Event::FirmwareIdentified(firmware.clone()));
                 self.firmware = Some(firmware)
             }
             FirmataMsg::ProtocolVersion { major, minor } => {
                 // Note that the protocol version is usually only sent when the Firmata device
                 // comes online. Because of this we can consider doing a state reset when this
-                // message is received.
-                self.reset();
-                self.protocol = Some(Protocol(major, minor))
+                // message is received. Only what the device reported is reset: the host's
+                // configuration is kept so that it can be replayed, and edge watches and
+                // histories are the application's, so they survive too.
+                use std::mem::take;
+                let modes = take(&mut self.modes);
+                let pins = take(&mut self.pins)
+                    .into_iter()
+                    .map(|(number, pin)| (number, Pin {
+                        mode: modes.get(&number).cloned(),
+                        reporting: pin.reporting,
+                        written: pin.written,
+                        history: pin.history,
+                        ..Pin::default()
+                    }))
+                    .filter(|(_, pin)| {
+                        pin.mode.is_some() || pin.reporting || pin.written.is_some() || pin.history.is_some()
+                    })
+                    .collect();
+                let ports = take(&mut self.ports)
+                    .into_iter()
+                    .filter(|(_, state)| state.written.is_some())
+                    .map(|(port, state)| (port, PortState { written: state.written, reported: None }))
+                    .collect();
+                *self = Board {
+                    protocol: Some(Protocol(major, minor)),
+                    pins,
+                    modes,
+                    analog_reports: take(&mut self.analog_reports),
+                    digital_reports: take(&mut self.digital_reports),
+                    ports,
+                    edges: take(&mut self.edges),
+                    ..Board::default()
+                };
+            }
+            FirmataMsg::CapabilityResponse(capabilities) => {
+                for (number, capabilities) in capabilities.into_iter().enumerate() {
+                    self.pins.entry(number as u8).or_default().capabilities = Some(capabilities);
+                }
+            }
+            FirmataMsg::AnalogMappingResponse(mapping) => {
+                for (number, &channel) in mapping.iter().enumerate() {
+                    let pin = self.pins.entry(number as u8).or_default();
+                    pin.analog_channel = channel;
+                    if let Some(channel) = channel {
+                        pin.reporting = self.analog_reports.contains(&channel);
+                    }
+                }
+                self.analog_mapping = mapping;
+            }
+            FirmataMsg::PinStateResponse(state) => {
+                // The state of an output is the value last written to it.
+                let value = state.state.min(u32::from(u16::MAX)) as u16;
+                let is_output = match state.mode {
+                    PinMode::DigitalOutput => {
+                        self.record_pin_write(state.pin, value != 0);
+                        true
+                    }
+                    PinMode::PWM | PinMode::Servo => {
+                        self.record_analog_write(state.pin, value);
+                        true
+                    }
+                    _ => false,
+                };
+                let pin = self.pins.entry(state.pin).or_default();
+                pin.mode = Some(state.mode);
+                if !is_output {
+                    pin.value = value;
+                }
+                pin.updated = Some(now);
+            }
+            FirmataMsg::StringData(string) => {
+                info!("Firmata device says: {}", string);
+                events.push(Event::StringData(string));
+            }
+            FirmataMsg::I2cReply { address, register, data } => {
+                events.push(Event::I2cReply { address, register, data });
             }
             _ => {
                 warn!("Unhandled firmata message {:?}", msg);
             }
         }
+        events
+    }
+}
+
+/// The pin numbers in a digital port.
+fn port_pins(port: u8) -> ::std::ops::Range<u8> {
+    let first = port.saturating_mul(8);
+    first..first.saturating_add(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use edges::Edge;
+    use protocol::PinState;
+
+    fn edges(events: Vec<Event>) -> Vec<(u8, Edge)> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Edge { pin, edge, .. } => Some((pin, edge)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_edges_of_watched_pins() {
+        let mut board = Board::default();
+        board.record_mode(2, PinMode::PullUp);
+        board.record_mode(3, PinMode::DigitalInput);
+        board.watch_edges(2, Trigger::Falling, None);
+        board.watch_edges(3, Trigger::Both, None);
+
+        let reports = [0x0C, 0x08, 0x00, 0x0C, 0x0C];
+        let seen: Vec<_> = reports
+            .iter()
+            .map(|&value| edges(board.update(FirmataMsg::DigitalRead { port: 0, value })))
+            .collect();
+
+        assert_eq!(seen, vec![
+            vec![],
+            vec![(2, Edge::Falling)],
+            vec![(3, Edge::Falling)],
+            vec![(3, Edge::Rising)],
+            vec![],
+        ]);
+    }
+
+    #[test]
+    fn records_history_of_reported_values() {
+        let mut board = Board::default();
+        board.keep_history(1, Limit::Samples(3));
+        board.keep_history(9, Limit::Samples(3));
+        let start = Instant::now();
+        for (ms, value) in (0..4).zip(&[100, 300, 200, 400]) {
+            board.update_at(FirmataMsg::AnalogRead { pin: 1, value: *value }, start + Duration::from_millis(ms));
+        }
+        board.update(FirmataMsg::DigitalRead { port: 1, value: 0x02 });
+        board.update(FirmataMsg::ProtocolVersion { major: 2, minor: 6 });
+
+        let history = board.pins[&1].history.as_ref().unwrap();
+        let stats = history.stats_since(start).unwrap();
+        assert_eq!((stats.min, stats.max, stats.mean, stats.count), (200, 400, 300.0, 3));
+        assert_eq!(board.pins[&9].history.as_ref().unwrap().latest().unwrap().value, 1);
+    }
+
+    #[test]
+    fn debounces_edges() {
+        let mut board = Board::default();
+        board.watch_edges(5, Trigger::Both, Some(Duration::from_millis(20)));
+        let start = Instant::now();
+        let mut feed = |ms, value| {
+            let msg = FirmataMsg::DigitalRead { port: 0, value };
+            edges(board.update_at(msg, start + Duration::from_millis(ms)))
+        };
+
+        assert_eq!(feed(0, 0x00), vec![]);
+        assert_eq!(feed(100, 0x20), vec![(5, Edge::Rising)]);
+        assert_eq!(feed(102, 0x00), vec![]);
+        assert_eq!(feed(104, 0x20), vec![]);
+        assert_eq!(feed(200, 0x00), vec![(5, Edge::Falling)]);
+        assert_eq!(feed(205, 0x20), vec![]);
+        assert_eq!(edges(board.poll_edges(start + Duration::from_millis(210))), vec![]);
+        assert_eq!(edges(board.poll_edges(start + Duration::from_millis(220))), vec![(5, Edge::Rising)]);
+    }
+
+    #[test]
+    fn tracks_pin_model() {
+        let mut board = Board::default();
+        board.update(FirmataMsg::CapabilityResponse(vec![
+            vec![PinCapability { mode: PinMode::DigitalInput, res: 1 }],
+            vec![
+                PinCapability { mode: PinMode::DigitalInput, res: 1 },
+                PinCapability { mode: PinMode::AnalogInput, res: 10 },
+            ],
+        ]));
+        board.update(FirmataMsg::AnalogMappingResponse(vec![None, Some(0)]));
+        board.record_mode(0, PinMode::DigitalInput);
+        board.record_analog_report(0, true);
+
+        let events = board.update(FirmataMsg::AnalogRead { pin: 0, value: 300 });
+        assert_eq!(events, vec![Event::PinChanged { pin: 1, value: 300 }]);
+        let events = board.update(FirmataMsg::DigitalRead { port: 0, value: 0x01 });
+        assert_eq!(events, vec![Event::PinChanged { pin: 0, value: 1 }]);
+
+        let pin = &board.pins[&1];
+        assert_eq!(pin.value, 300);
+        assert_eq!(pin.analog_channel, Some(0));
+        assert_eq!(pin.resolution(PinMode::AnalogInput), Some(10));
+        assert!(pin.reporting);
+        assert!(pin.updated.is_some());
+        assert_eq!(board.pins[&0].mode, Some(PinMode::DigitalInput));
+        assert_eq!(board.pin_count(), 2);
+
+        board.record_port_write(0, 0x02);
+        assert_eq!(board.pins[&0].written, Some(0));
+        assert_eq!(board.pins[&1].written, Some(1));
+    }
+
+    #[test]
+    fn keeps_host_configuration_across_protocol_version() {
+        let mut board = Board::default();
+        board.update(FirmataMsg::QueryFirmware { major: 2, minor: 5, firmware_name: b"Test".to_vec() });
+        board.update(FirmataMsg::CapabilityResponse(vec![
+            vec![PinCapability { mode: PinMode::DigitalOutput, res: 1 }],
+            vec![PinCapability { mode: PinMode::AnalogInput, res: 10 }],
+        ]));
+        board.record_mode(0, PinMode::DigitalOutput);
+        board.record_port_write(0, 0x01);
+        board.record_analog_report(1, true);
+        board.record_digital_report(0, true);
+        board.update(FirmataMsg::DigitalRead { port: 0, value: 0x01 });
+        board.update(FirmataMsg::ProtocolVersion { major: 2, minor: 5 });
+
+        assert_eq!(board.firmware, None);
+        assert_eq!(board.pin_count(), 0);
+        assert_eq!(board.modes[&0], PinMode::DigitalOutput);
+        assert!(board.analog_reports.contains(&1));
+        assert!(board.digital_reports.contains(&0));
+        assert_eq!(board.port_state(0), Some(PortState { written: Some(0x01), reported: None }));
+        assert_eq!(board.pins[&0].mode, Some(PinMode::DigitalOutput));
+        assert_eq!(board.pins[&0].written, Some(1));
+        assert!(board.pins[&1].reporting);
+    }
+
+    #[test]
+    fn tracks_port_state() {
+        let mut board = Board::default();
+        board.record_mode(9, PinMode::DigitalOutput);
+        board.record_port_write(1, 0x01);
+        board.update(FirmataMsg::DigitalRead { port: 1, value: 0x04 });
+
+        assert_eq!(board.port_state(1), Some(PortState { written: Some(0x01), reported: Some(0x04) }));
+        assert_eq!(board.digital_read(8), Some(false));
+        assert_eq!(board.digital_read(9), Some(false));
+        assert_eq!(board.digital_read(10), Some(true));
+        assert_eq!(board.digital_read(16), None);
+
+        board.record_pin_write(9, true);
+        assert_eq!(board.port_state(1).unwrap().written, Some(0x03));
+        assert_eq!(board.digital_read(9), Some(true));
+        assert_eq!(board.port_value_with(8, false), 0x02);
+    }
+
+    #[test]
+    fn reports_levels_of_pins_not_yet_known() {
+        let mut board = Board::default();
+        board.record_digital_report(0, true);
+        board.watch_edges(3, Trigger::Rising, None);
+
+        let events = board.update(FirmataMsg::DigitalRead { port: 0, value: 0x00 });
+        assert_eq!(events.len(), 8);
+        let events = board.update(FirmataMsg::DigitalRead { port: 0, value: 0x08 });
+        assert_eq!(edges(events.clone()), vec![(3, Edge::Rising)]);
+        assert!(events.contains(&Event::PinChanged { pin: 3, value: 1 }));
+        assert_eq!(board.pins[&3].value, 1);
+        assert!(board.pins[&5].reporting);
+    }
+
+    #[test]
+    fn records_queried_pin_states() {
+        let mut board = Board::default();
+        let state = |pin, mode, state| FirmataMsg::PinStateResponse(PinState { pin, mode, state });
+        board.update(state(13, PinMode::DigitalOutput, 1));
+        board.update(state(9, PinMode::PWM, 128));
+        board.update(state(2, PinMode::AnalogInput, 0));
+
+        assert_eq!(board.pins[&13].written, Some(1));
+        assert_eq!(board.digital_read(13), Some(true));
+        assert_eq!(board.pins[&9].written, Some(128));
+        assert_eq!(board.pins[&9].mode, Some(PinMode::PWM));
+        assert_eq!(board.pins[&2].written, None);
+        assert!(board.pins[&2].updated.is_some());
     }
 }
@@ -35,7 +35,7 @@ where
                         debug!("Firmata read timed out, retrying ({} of {})", retries, max_retries);
                     },
                     _ => {
-                        error!("Unable to read Firmata message: {:?}", kind);
+                        error!("Unable to read Firmata message: {:?}", err);
                         break Err(err.into())
                     }
                 }
@@ -58,6 +58,14 @@ where
     buf.resize(len, 0);
 
     match conn.read(&mut buf[len - 1..]) {
+        Ok(0) => {
+            // Drop the byte reserved for this read so a retry doesn't parse it.
+            buf.truncate(len - 1);
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Firmata stream closed",
+            ).into())
+        }
         Ok(_) => match parser::parse(&buf[..]) {
             Ok((_, msg)) => {
                 debug!("Parse complete. Message: {:?}", msg);
@@ -72,41 +80,66 @@ where
                 Err(ErrorKind::UnreadableMsg.into())
             }
         },
-        Err(e) => Err(e.into())
+        Err(e) => {
+            buf.truncate(len - 1);
+            Err(e.into())
+        }
     }
 }
 
 pub fn reset<T>(conn: &mut T) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
     conn.write_all(&[RESET])
 }
 
 pub fn query_firmware<T>(conn: &mut T) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
     conn.write_all(&[START_SYSEX, QUERY_FIRMWARE, END_SYSEX])
 }
 
+/// Request a `ProtocolVersion` report from the device.
+pub fn query_version<T>(conn: &mut T) -> io::Result<()>
+where
+    T: io::Write,
+{
+    conn.write_all(&[PROTOCOL_VERSION])
+}
+
 pub fn capabilities<T>(conn: &mut T) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
     conn.write_all(&[START_SYSEX, CAPABILITY_QUERY, END_SYSEX])
 }
 
+pub fn analog_mapping<T>(conn: &mut T) -> io::Result<()>
+where
+    T: io::Write,
+{
+    conn.write_all(&[START_SYSEX, ANALOG_MAPPING_QUERY, END_SYSEX])
+}
+
+pub fn pin_state<T>(conn: &mut T, pin: u8) -> io::Result<()>
+where
+    T: io::Write,
+{
+    conn.write_all(&[START_SYSEX, PIN_STATE_QUERY, pin, END_SYSEX])
+}
+
 pub fn set_pin_mode<T>(conn: &mut T, pin: u8, mode: PinMode) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
-    conn.write_all(&[START_SYSEX, SET_PIN_MODE, pin, mode.into(), END_SYSEX])
+    conn.write_all(&[SET_PIN_MODE, pin, mode.into()])
 }
 
 pub fn analog_report<T>(conn: &mut T, pin: u8, state: bool) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
     if pin >= 16 {
         Err(io::Error::new(
@@ -119,10 +152,59 @@ where
     }
 }
 
+/// Enable or disable reporting of the levels of a digital port.
+pub fn digital_report<T>(conn: &mut T, port: u8, state: bool) -> io::Result<()>
+where
+    T: io::Write,
+{
+    if port >= 16 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "port index >= 16",
+        ))
+    } else {
+        let mode: u8 = if state { 1 } else { 0 };
+        conn.write_all(&[DIGITAL_REPORT | port, mode])
+    }
+}
+
+/// Write a PWM duty cycle or servo position to a pin.
+///
+/// Pins above 15 can't be addressed by an analog message, so they are
+/// written with an extended analog sysex message instead.
+pub fn analog_write<T>(conn: &mut T, pin: u8, value: u16) -> io::Result<()>
+where
+    T: io::Write,
+{
+    let lsb = (value & 0x7F) as u8;
+    let msb = ((value >> 7) & 0x7F) as u8;
+    if pin < 16 {
+        conn.write_all(&[ANALOG_MESSAGE << 4 | pin, lsb, msb])
+    } else {
+        let mut msg = vec![START_SYSEX, EXTENDED_ANALOG, pin, lsb, msb];
+        if value >> 14 != 0 {
+            msg.push((value >> 14) as u8);
+        }
+        msg.push(END_SYSEX);
+        conn.write_all(&msg)
+    }
+}
+
+/// Set the level of a single digital pin.
+///
+/// This message was added in protocol version 2.5; older firmware only
+/// understands `digital_port_write`.
+pub fn digital_write<T>(conn: &mut T, pin: u8, level: bool) -> io::Result<()>
+where
+    T: io::Write,
+{
+    conn.write_all(&[DIGITAL_WRITE, pin, level as u8])
+}
+
 /// Write a value to a port register of the Firmata board.
 pub fn digital_port_write<T>(conn: &mut T, port: u8, value: u8) -> io::Result<()>
 where
-    T: ::connection::RW,
+    T: io::Write,
 {
     if port >= 16 {
         Err(io::Error::new(
@@ -137,14 +219,68 @@ where
     }
 }
 
+/// Enable I2C on the device, waiting `delay_us` microseconds between
+/// writing a register and reading it back, for devices that need it.
+/// The delay is sent as 14 bits, so it must be less than 16384.
+pub fn i2c_config<T>(conn: &mut T, delay_us: u16) -> io::Result<()>
+where
+    T: io::Write,
+{
+    if delay_us >> 14 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "I2C read delay >= 16384 us",
+        ));
+    }
+    conn.write_all(&[START_SYSEX, I2C_CONFIG, (delay_us & 0x7F) as u8, ((delay_us >> 7) & 0x7F) as u8, END_SYSEX])
+}
+
+/// Write bytes to the I2C device at a 7 bit address.
+pub fn i2c_write<T>(conn: &mut T, address: u8, data: &[u8]) -> io::Result<()>
+where
+    T: io::Write,
+{
+    i2c_request(conn, address, I2C_MODE_WRITE, data.iter().map(|&byte| u16::from(byte)))
+}
+
+/// Read `len` bytes from the I2C device at a 7 bit address, starting at
+/// `register` if given. The device answers with an `I2cReply`.
+pub fn i2c_read<T>(conn: &mut T, address: u8, register: Option<u16>, len: u16) -> io::Result<()>
+where
+    T: io::Write,
+{
+    i2c_request(conn, address, I2C_MODE_READ_ONCE, register.into_iter().chain(Some(len)))
+}
+
+fn i2c_request<T, I>(conn: &mut T, address: u8, mode: u8, data: I) -> io::Result<()>
+where
+    T: io::Write,
+    I: IntoIterator<Item = u16>,
+{
+    if address >= 0x80 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "I2C address >= 0x80",
+        ));
+    }
+    let mut msg = vec![START_SYSEX, I2C_REQUEST, address, mode];
+    for value in data {
+        msg.push((value & 0x7F) as u8);
+        msg.push(((value >> 7) & 0x7F) as u8);
+    }
+    msg.push(END_SYSEX);
+    conn.write_all(&msg)
+}
+
 /// Resynchronize the serial connection to the Firmata device.
 ///
 /// A firmata device can be in an arbitrary state when we initially connect,
 /// and we may have a buffer of stale information that needs to be cleaned.
 /// The easiest way to resolve this is to send several reset messages and drain
 /// the serial buffer until we receive the ProtocolVersion message, which means
-/// the device is in a known good state.
-pub fn resync<T>(conn: &mut T) -> io::Result<()>
+/// the device is in a known good state. That message is returned so that it
+/// can be applied to the board state.
+pub fn resync<T>(conn: &mut T) -> io::Result<FirmataMsg>
 where
     T: ::connection::RW,
 {
@@ -162,10 +298,10 @@ where
 
         for _ in 0.. 30 {
             match read(conn) {
-                Ok(FirmataMsg::ProtocolVersion { .. }) |
-                Ok(FirmataMsg::QueryFirmware { .. }) => {
+                Ok(msg @ FirmataMsg::ProtocolVersion { .. }) |
+                Ok(msg @ FirmataMsg::QueryFirmware { .. }) => {
                     debug!("Firmata connection resynchronized.");
-                    return Ok(())
+                    return Ok(msg)
                 }
                 Ok(m) => {
                     trace!("Discarding message {:?}", m);
@@ -179,3 +315,25 @@ where
         "Could not resynchronize Firmata connection",
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_pin_mode_is_a_three_byte_command() {
+        let mut conn = io::Cursor::new(Vec::new());
+        set_pin_mode(&mut conn, 13, PinMode::DigitalOutput).unwrap();
+        set_pin_mode(&mut conn, 3, PinMode::PWM).unwrap();
+        assert_eq!(conn.into_inner(), [SET_PIN_MODE, 13, 0x01, SET_PIN_MODE, 3, 0x03]);
+    }
+
+    #[test]
+    fn i2c_config_sends_a_14_bit_delay() {
+        let mut conn = io::Cursor::new(Vec::new());
+        i2c_config(&mut conn, 0x3FFF).unwrap();
+        assert_eq!(conn.get_ref()[..], [START_SYSEX, I2C_CONFIG, 0x7F, 0x7F, END_SYSEX]);
+        assert_eq!(i2c_config(&mut conn, 0x4000).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(conn.into_inner().len(), 5);
+    }
+}
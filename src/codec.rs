@@ -0,0 +1,93 @@
+//! A `tokio_util` codec for the messages sent by a Firmata device.
+
+use bytes::{Buf, BytesMut};
+use errors::*;
+use nom;
+use parser;
+use protocol::FirmataMsg;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes `FirmataMsg`s from a device stream and encodes them back into
+/// the device's wire format.
+///
+/// Bytes that can't start a known message are discarded one at a time until
+/// the stream lines up with a message boundary again, the same way
+/// `client::resync` drains a stale serial buffer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FirmataCodec;
+
+impl FirmataCodec {
+    pub fn new() -> Self {
+        FirmataCodec
+    }
+}
+
+impl Decoder for FirmataCodec {
+    type Item = FirmataMsg;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<FirmataMsg>> {
+        while !src.is_empty() {
+            let consumed = match parser::parse(&src[..]) {
+                Ok((rest, msg)) => Some((src.len() - rest.len(), msg)),
+                Err(nom::Err::Incomplete(_)) => return Ok(None),
+                Err(_) => None,
+            };
+            match consumed {
+                Some((len, msg)) => {
+                    src.advance(len);
+                    return Ok(Some(msg));
+                }
+                None => {
+                    trace!("Discarding unparseable byte {:#04X}", src[0]);
+                    src.advance(1);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<FirmataMsg> for FirmataCodec {
+    type Error = Error;
+
+    fn encode(&mut self, msg: FirmataMsg, dst: &mut BytesMut) -> Result<()> {
+        dst.extend_from_slice(&msg.encode());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_split_and_garbled_input() {
+        let mut codec = FirmataCodec::new();
+        let mut buf = BytesMut::from(&b"\x20\x85\xF9\x02"[..]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"\x06\xE1\x10");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(FirmataMsg::ProtocolVersion { major: 2, minor: 6 })
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"\x01");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(FirmataMsg::AnalogRead { pin: 1, value: 144 })
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encodes_messages() {
+        let mut buf = BytesMut::new();
+        FirmataCodec::new()
+            .encode(FirmataMsg::ProtocolVersion { major: 2, minor: 6 }, &mut buf)
+            .unwrap();
+
+        assert_eq!(&buf[..], b"\xF9\x02\x06");
+    }
+}
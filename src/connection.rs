@@ -1,9 +1,12 @@
 //! A persistent connection to a Firmata board, associated with the state
 //! of that board.
 
-use ::board::Board;
+use ::board::{Board, Firmware, Protocol};
 use ::errors::*;
+use ::protocol::{FirmataMsg, PinCapability, PinState};
+use ::tcp::TcpPort;
 use std::{io, time};
+use std::net::ToSocketAddrs;
 
 use serial_unix;
 use serial_core as serial;
@@ -17,6 +20,56 @@ const SERIAL_SETTINGS: serial::PortSettings = serial::PortSettings {
     flow_control: serial::FlowNone,
 };
 
+const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_millis(5);
+
+/// Baud rates tried by `ConnectionBuilder::open_detect`, most common first.
+pub const COMMON_BAUD_RATES: &[usize] = &[57600, 115200, 9600, 19200, 38400, 250000];
+
+/// How long to wait for an answer at each baud rate. Long enough for an
+/// Uno, which resets when the port is opened, to boot into its sketch.
+const DETECT_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// The longest time spent discarding bytes sent at the previous baud rate.
+const DRAIN_LIMIT: time::Duration = time::Duration::from_millis(100);
+
+/// How long `Connection::initialize` waits for each query to be answered.
+pub const DEFAULT_QUERY_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+/// A query made by `Connection::initialize` or `Connection::setup`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InitStep {
+    ProtocolVersion,
+    Firmware,
+    Capabilities,
+    AnalogMapping,
+    PinState(u8),
+}
+
+/// The outcome of `Connection::initialize`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InitReport {
+    /// The queries the firmware did not answer in time.
+    pub unanswered: Vec<InitStep>,
+}
+
+impl InitReport {
+    pub fn is_complete(&self) -> bool {
+        self.unanswered.is_empty()
+    }
+
+    /// Record a step if it timed out, passing on any other error.
+    fn record<R>(&mut self, step: InitStep, answer: Result<R>) -> Result<()> {
+        match answer {
+            Ok(_) => Ok(()),
+            Err(Error(ErrorKind::ResponseTimeout, _)) => {
+                self.unanswered.push(step);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
 pub trait RW: io::Read + io::Write + Send {}
 
 impl<T> RW for T
@@ -25,6 +78,8 @@ where
 {
 }
 
+// A connection is long lived and rarely moved, so the board isn't boxed.
+#[allow(clippy::large_enum_variant)]
 pub enum Connection<T>
 where
     T: RW
@@ -43,6 +98,39 @@ where
         Ok(Connection::Open { inner, board: Board::default() })
     }
 
+    /// Close the connection, returning the state of the board it was attached to.
+    pub fn close(&mut self) -> Option<Board> {
+        match ::std::mem::replace(self, Connection::Closed) {
+            Connection::Open { board, .. } => Some(board),
+            Connection::Closed => None
+        }
+    }
+
+    /// Reissue the pin modes and reporting subscriptions recorded in the board.
+    ///
+    /// A Firmata device forgets its configuration when it resets, so this
+    /// restores the host's view of the device after reconnecting.
+    pub fn replay(&mut self) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref board } => {
+                for (&pin, &mode) in &board.modes {
+                    debug!("Restoring pin {} to {:?}", pin, mode);
+                    ::client::set_pin_mode(inner, pin, mode)?;
+                }
+                for &pin in &board.analog_reports {
+                    debug!("Restoring analog reporting on pin {}", pin);
+                    ::client::analog_report(inner, pin, true)?;
+                }
+                for &port in &board.digital_reports {
+                    debug!("Restoring digital reporting on port {}", port);
+                    ::client::digital_report(inner, port, true)?;
+                }
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
     pub fn board(&self) -> Option<&Board> {
         match *self {
             Connection::Open { ref board, .. } => Some(board),
@@ -50,6 +138,13 @@ where
         }
     }
 
+    pub fn board_mut(&mut self) -> Option<&mut Board> {
+        match *self {
+            Connection::Open { ref mut board, .. } => Some(board),
+            Connection::Closed => None
+        }
+    }
+
     pub fn conn(&self) -> Option<&RW> {
         match *self {
             Connection::Open { ref inner, .. } => Some(inner),
@@ -75,65 +170,324 @@ where
     }
 
     pub fn resync(&mut self) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                let msg = ::client::resync(inner)?;
+                board.update(msg);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn set_pin_mode(&mut self, port: u8, mode: ::protocol::PinMode) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                ::client::set_pin_mode(inner, port, mode)?;
+                board.record_mode(port, mode);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn digital_port_write(&mut self, port: u8, value: u8) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                ::client::digital_port_write(inner, port, value)?;
+                board.record_port_write(port, value);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    /// Set the level of a single digital pin.
+    ///
+    /// Firmware older than protocol version 2.5 can only write whole ports,
+    /// so the pin's port is written with the other pins left at the levels
+    /// the host last wrote to them.
+    pub fn digital_write(&mut self, pin: u8, level: bool) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                if board.supports_pin_write() {
+                    ::client::digital_write(inner, pin, level)?;
+                } else {
+                    ::client::digital_port_write(inner, pin / 8, board.port_value_with(pin, level))?;
+                }
+                board.record_pin_write(pin, level);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn analog_write(&mut self, pin: u8, value: u16) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                ::client::analog_write(inner, pin, value)?;
+                board.record_analog_write(pin, value);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn digital_report(&mut self, port: u8, state: bool) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                ::client::digital_report(inner, port, state)?;
+                board.record_digital_report(port, state);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn analog_report(&mut self, pin: u8, state: bool) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, ref mut board } => {
+                ::client::analog_report(inner, pin, state)?;
+                board.record_analog_report(pin, state);
+                Ok(())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn query_firmware(&mut self) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::resync(inner)
+                ::client::query_firmware(inner)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
-    pub fn set_pin_mode(&mut self, port: u8, mode: ::protocol::PinMode) -> Result<()> {
+    pub fn query_version(&mut self) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::set_pin_mode(inner, port, mode)
+                ::client::query_version(inner)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
-    pub fn digital_port_write(&mut self, port: u8, value: u8) -> Result<()> {
+    pub fn capabilities(&mut self) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::digital_port_write(inner, port, value)
+                ::client::capabilities(inner)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
-    pub fn analog_report(&mut self, pin: u8, state: bool) -> Result<()> {
+    pub fn analog_mapping(&mut self) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::analog_report(inner, pin, state)
+                ::client::analog_mapping(inner)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
-    pub fn query_firmware(&mut self) -> Result<()> {
+    pub fn pin_state(&mut self, pin: u8) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::query_firmware(inner)
+                ::client::pin_state(inner, pin)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
-    pub fn capabilities(&mut self) -> Result<()> {
+    pub fn i2c_config(&mut self, delay_us: u16) -> Result<()> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
-                ::client::capabilities(inner)
+                ::client::i2c_config(inner, delay_us)
+                    .map_err(|e| e.into())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    pub fn i2c_write(&mut self, address: u8, data: &[u8]) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, .. } => {
+                ::client::i2c_write(inner, address, data)
                     .map_err(|e| e.into())
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
     }
 
+    pub fn i2c_read(&mut self, address: u8, register: Option<u16>, len: u16) -> Result<()> {
+        match *self {
+            Connection::Open { ref mut inner, .. } => {
+                ::client::i2c_read(inner, address, register, len)
+                    .map_err(|e| e.into())
+            },
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    /// Read from an I2C device and wait for the reply.
+    ///
+    /// StandardFirmata replies even when the device doesn't answer, with
+    /// fewer bytes than were asked for, so the reply may be short.
+    pub fn query_i2c_read(&mut self, address: u8, register: Option<u16>, len: u16, timeout: time::Duration) -> Result<Vec<u8>> {
+        self.i2c_read(address, register, len)?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::I2cReply { address: from, ref data, .. } if from == u16::from(address) => Some(data.clone()),
+            _ => None,
+        })
+    }
+
+    /// Query the capabilities of every pin and wait for the response.
+    pub fn query_capabilities(&mut self, timeout: time::Duration) -> Result<Vec<Vec<PinCapability>>> {
+        self.capabilities()?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::CapabilityResponse(ref pins) => Some(pins.clone()),
+            _ => None,
+        })
+    }
+
+    /// Query the firmware name and version and wait for the response.
+    pub fn query_firmware_info(&mut self, timeout: time::Duration) -> Result<Firmware> {
+        self.query_firmware()?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::QueryFirmware { major, minor, ref firmware_name } => Some(Firmware {
+                major,
+                minor,
+                name: String::from_utf8_lossy(firmware_name).into(),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Query the protocol version and wait for the response.
+    ///
+    /// The board model is reset when the version arrives, as it is when the
+    /// device announces itself, so query it before `initialize`.
+    pub fn query_protocol_version(&mut self, timeout: time::Duration) -> Result<Protocol> {
+        self.query_version()?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::ProtocolVersion { major, minor } => Some(Protocol(major, minor)),
+            _ => None,
+        })
+    }
+
+    /// Query the analog channel of every pin and wait for the response.
+    pub fn query_analog_mapping(&mut self, timeout: time::Duration) -> Result<Vec<Option<u8>>> {
+        self.analog_mapping()?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::AnalogMappingResponse(ref channels) => Some(channels.clone()),
+            _ => None,
+        })
+    }
+
+    /// Query the mode and state of a pin and wait for the response.
+    pub fn query_pin_state(&mut self, pin: u8, timeout: time::Duration) -> Result<PinState> {
+        self.pin_state(pin)?;
+        self.wait_for(timeout, |msg| match *msg {
+            FirmataMsg::PinStateResponse(ref state) if state.pin == pin => Some(state.clone()),
+            _ => None,
+        })
+    }
+
+    /// Build a complete model of the board with the default query timeout.
+    pub fn initialize(&mut self) -> Result<InitReport> {
+        self.initialize_with(DEFAULT_QUERY_TIMEOUT)
+    }
+
+    /// Build a complete model of the board by querying the firmware, the
+    /// pin capabilities, the analog mapping, and the state of every pin.
+    ///
+    /// Firmware that doesn't implement a query is not an error; each query
+    /// that goes unanswered within `timeout` is listed in the report, and
+    /// the rest of the model is still filled in.
+    pub fn initialize_with(&mut self, timeout: time::Duration) -> Result<InitReport> {
+        let mut report = InitReport::default();
+
+        let answer = self.query_firmware_info(timeout);
+        report.record(InitStep::Firmware, answer)?;
+        let answer = self.query_capabilities(timeout);
+        report.record(InitStep::Capabilities, answer)?;
+        let answer = self.query_analog_mapping(timeout);
+        report.record(InitStep::AnalogMapping, answer)?;
+
+        let pins: Vec<u8> = match self.board() {
+            Some(board) => {
+                let mut pins: Vec<u8> = board.pins
+                    .iter()
+                    .filter(|&(_, pin)| pin.capabilities.as_ref().is_some_and(|modes| !modes.is_empty()))
+                    .map(|(&number, _)| number)
+                    .collect();
+                pins.sort();
+                pins
+            }
+            None => Vec::new(),
+        };
+        for pin in pins {
+            let answer = self.query_pin_state(pin, timeout);
+            report.record(InitStep::PinState(pin), answer)?;
+        }
+
+        if !report.is_complete() {
+            warn!("Firmata device did not answer {:?}", report.unanswered);
+        }
+        Ok(report)
+    }
+
+    /// Resynchronize with the device, ask for its protocol version and build
+    /// a complete model of the board, which is everything a program needs
+    /// before showing or driving a board it just opened.
+    pub fn setup(&mut self) -> Result<InitReport> {
+        self.resync()?;
+        let mut report = InitReport::default();
+        // The version resets the board model, so it goes before initialize.
+        let answer = self.query_protocol_version(DEFAULT_QUERY_TIMEOUT);
+        report.record(InitStep::ProtocolVersion, answer)?;
+        report.unanswered.extend(self.initialize()?.unanswered);
+        Ok(report)
+    }
+
+    /// Read messages until `select` picks a response out of one, or until
+    /// `timeout` passes.
+    ///
+    /// Every message read, including the response, is applied to the board
+    /// so that reports arriving in the meantime aren't lost.
+    fn wait_for<F, R>(&mut self, timeout: time::Duration, mut select: F) -> Result<R>
+    where
+        F: FnMut(&FirmataMsg) -> Option<R>
+    {
+        let deadline = time::Instant::now() + timeout;
+        while time::Instant::now() < deadline {
+            match self.read() {
+                Ok(msg) => {
+                    let response = select(&msg);
+                    if let Some(board) = self.board_mut() {
+                        board.update(msg);
+                    }
+                    if let Some(response) = response {
+                        return Ok(response);
+                    }
+                }
+                Err(e) => {
+                    if ::reconnect::is_disconnect(&e) {
+                        return Err(e);
+                    }
+                    trace!("Serial read returned error {:?}", e);
+                }
+            }
+        }
+        Err(ErrorKind::ResponseTimeout.into())
+    }
+
     pub fn read(&mut self) -> Result<::protocol::FirmataMsg> {
         match *self {
             Connection::Open { ref mut inner, .. } => {
@@ -143,12 +497,12 @@ where
         }
     }
 
-    pub fn update(&mut self) -> Result<()> {
+    /// Read a message from the device and apply it to the board, returning
+    /// the events it caused.
+    pub fn update(&mut self) -> Result<Vec<::events::Event>> {
         match *self {
             Connection::Open { ref mut inner, ref mut board } => {
-                ::client::read(inner).and_then(|msg| {
-                    board.update(msg); Ok(())
-                })
+                ::client::read(inner).map(|msg| board.update(msg))
             },
             Connection::Closed => Err(ErrorKind::ConnectionClosed.into())
         }
@@ -157,14 +511,395 @@ where
 
 impl Connection<serial_unix::TTYPort>
 {
+    /// Open a serial connection with the default settings, 57600 baud 8N1.
     pub fn open(path: &str) -> Result<Self> {
+        ConnectionBuilder::new().open(path)
+    }
+
+    /// The baud rate the serial port is currently configured for.
+    pub fn baud_rate(&self) -> Option<usize> {
+        use serial_core::SerialDevice;
+        match *self {
+            Connection::Open { ref inner, .. } => inner
+                .read_settings()
+                .ok()
+                .and_then(|settings| settings.baud_rate())
+                .map(|rate| rate.speed()),
+            Connection::Closed => None
+        }
+    }
+}
+
+/// Serial port settings used to open a `Connection`.
+///
+/// The defaults match StandardFirmata: 57600 baud 8N1 with a 5 ms read
+/// timeout, and the DTR and RTS lines left as the operating system set them.
+#[derive(Debug, Clone)]
+pub struct ConnectionBuilder {
+    settings: serial::PortSettings,
+    timeout: time::Duration,
+    dtr: Option<bool>,
+    rts: Option<bool>,
+    reset_on_open: bool,
+    detect_timeout: time::Duration,
+}
+
+impl Default for ConnectionBuilder {
+    fn default() -> Self {
+        ConnectionBuilder {
+            settings: SERIAL_SETTINGS,
+            timeout: DEFAULT_TIMEOUT,
+            dtr: None,
+            rts: None,
+            reset_on_open: false,
+            detect_timeout: DETECT_TIMEOUT,
+        }
+    }
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> Self {
+        ConnectionBuilder::default()
+    }
+
+    /// Set the baud rate the Firmata sketch was built with.
+    pub fn baud_rate(mut self, rate: usize) -> Self {
+        self.settings.baud_rate = serial::BaudRate::from_speed(rate);
+        self
+    }
+
+    /// Set how long a single read may block before timing out.
+    pub fn timeout(mut self, timeout: time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Drive the DTR line to the given level after opening the port.
+    pub fn dtr(mut self, level: bool) -> Self {
+        self.dtr = Some(level);
+        self
+    }
+
+    /// Drive the RTS line to the given level after opening the port.
+    pub fn rts(mut self, level: bool) -> Self {
+        self.rts = Some(level);
+        self
+    }
+
+    /// Pulse DTR after opening the port to reset the board into its bootloader.
+    ///
+    /// Most Arduino boards reset when DTR is toggled. This is disabled by
+    /// default; leave it off for boards that should keep running across
+    /// connections. With it off, `open` clears HUPCL on the port so that
+    /// closing it leaves DTR raised and later opens don't reset the board.
+    ///
+    /// The operating system raises DTR when a closed port is opened, so a
+    /// board still resets on the first open after it is plugged in, and
+    /// on the first open after a program that left HUPCL set.
+    pub fn reset_on_open(mut self, reset: bool) -> Self {
+        self.reset_on_open = reset;
+        self
+    }
+
+    /// Set how long `open_detect` waits for an answer at each baud rate.
+    pub fn detect_timeout(mut self, timeout: time::Duration) -> Self {
+        self.detect_timeout = timeout;
+        self
+    }
+
+    pub fn open(&self, path: &str) -> Result<Connection<serial_unix::TTYPort>> {
         use ::std::path::Path;
+        use ::std::thread;
         serial_unix::TTYPort::open(Path::new(path))
             .and_then(|mut inner| {
-                inner.set_timeout(time::Duration::from_millis(5))?;
-                inner.configure(&SERIAL_SETTINGS)?;
+                inner.set_timeout(self.timeout)?;
+                inner.configure(&self.settings)?;
+                if self.reset_on_open {
+                    debug!("Resetting Firmata device on {} with DTR", path);
+                    inner.set_dtr(false)?;
+                    thread::sleep(time::Duration::from_millis(100));
+                    inner.set_dtr(true)?;
+                } else {
+                    keep_dtr_on_close(&inner)?;
+                }
+                if let Some(level) = self.dtr {
+                    inner.set_dtr(level)?;
+                }
+                if let Some(level) = self.rts {
+                    inner.set_rts(level)?;
+                }
                 Ok(Connection::Open { inner, board: Board::default() })
             })
             .map_err(|err| err.into())
     }
+
+    /// Open a serial connection, detecting the baud rate of the Firmata device.
+    ///
+    /// Each rate is tried in order by querying the firmware and waiting up
+    /// to the detect timeout for a `QueryFirmware` or `ProtocolVersion`
+    /// response. The first rate that produces one is kept; use
+    /// `Connection::baud_rate` to find out which.
+    pub fn open_detect(&self, path: &str, rates: &[usize]) -> Result<Connection<serial_unix::TTYPort>> {
+        let mut conn = self.open(path)?;
+        for &rate in rates {
+            debug!("Probing Firmata device on {} at {} baud", path, rate);
+            if let Connection::Open { ref mut inner, .. } = conn {
+                inner.reconfigure(&|settings| {
+                    settings.set_baud_rate(serial::BaudRate::from_speed(rate))
+                })?;
+            }
+            if detect(&mut conn, self.detect_timeout)? {
+                info!("Detected Firmata device on {} at {} baud", path, rate);
+                return Ok(conn);
+            }
+        }
+        Err(ErrorKind::BaudRateNotDetected.into())
+    }
+}
+
+/// Clear HUPCL so the driver doesn't drop DTR, resetting the board, when
+/// the port is closed.
+fn keep_dtr_on_close(port: &serial_unix::TTYPort) -> io::Result<()> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+    let fd = port.as_raw_fd();
+    unsafe {
+        let mut termios: libc::termios = ::std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        termios.c_cflag &= !libc::HUPCL;
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Query the firmware and check whether the device answers with a valid
+/// response within `timeout`.
+fn detect<T>(conn: &mut Connection<T>, timeout: time::Duration) -> Result<bool>
+where
+    T: RW
+{
+    if let Connection::Open { ref mut inner, ref mut board } = *conn {
+        // Bytes sent at the previous rate are garbage at this one.
+        discard_input(inner)?;
+        *board = Board::default();
+        ::client::query_firmware(inner)?;
+    }
+    let answered = conn.wait_for(timeout, |msg| match *msg {
+        FirmataMsg::QueryFirmware { .. } | FirmataMsg::ProtocolVersion { .. } => Some(()),
+        _ => None,
+    });
+    match answered {
+        Ok(()) => Ok(true),
+        Err(Error(ErrorKind::ResponseTimeout, _)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read and throw away whatever the device has sent, until it goes quiet
+/// or `DRAIN_LIMIT` passes.
+fn discard_input<T>(inner: &mut T) -> Result<()>
+where
+    T: RW
+{
+    let deadline = time::Instant::now() + DRAIN_LIMIT;
+    let mut buf = [0; 64];
+    while time::Instant::now() < deadline {
+        match inner.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => trace!("Discarding {} bytes", n),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+impl Connection<TcpPort>
+{
+    /// Connect to an EthernetFirmata or WiFiFirmata device.
+    ///
+    /// These sketches listen on `tcp::DEFAULT_PORT` unless they were
+    /// configured otherwise.
+    pub fn connect_tcp<A>(addr: A) -> Result<Self>
+    where A: ToSocketAddrs
+    {
+        TcpPort::connect(addr, DEFAULT_TIMEOUT)
+            .map(|inner| Connection::Open { inner, board: Board::default() })
+            .map_err(|err| err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+    #[test]
+    fn query_returns_response_and_updates_board() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 3];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&FirmataMsg::AnalogRead { pin: 0, value: 512 }.encode()).unwrap();
+            stream.write_all(&FirmataMsg::AnalogMappingResponse(vec![None, Some(0)]).encode()).unwrap();
+            request
+        });
+
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        let mapping = conn.query_analog_mapping(TIMEOUT).unwrap();
+
+        assert_eq!(mapping, vec![None, Some(0)]);
+        assert_eq!(device.join().unwrap(), [START_SYSEX, ANALOG_MAPPING_QUERY, END_SYSEX]);
+        assert_eq!(conn.board().unwrap().pins[&0].value, 512);
+    }
+
+    #[test]
+    fn initialize_reports_unanswered_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 3];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"\xF0\x79\x02\x06Test\xF7").unwrap();
+
+            stream.read_exact(&mut request).unwrap();
+            let capabilities = vec![
+                vec![],
+                vec![PinCapability { mode: PinMode::AnalogInput, res: 10 }],
+            ];
+            stream.write_all(&FirmataMsg::CapabilityResponse(capabilities).encode()).unwrap();
+
+            // Leave the analog mapping query unanswered.
+            stream.read_exact(&mut request).unwrap();
+
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            let state = PinState { pin: 1, mode: PinMode::AnalogInput, state: 0 };
+            stream.write_all(&FirmataMsg::PinStateResponse(state).encode()).unwrap();
+            request
+        });
+
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        let report = conn.initialize_with(time::Duration::from_millis(100)).unwrap();
+
+        assert_eq!(report.unanswered, vec![InitStep::AnalogMapping]);
+        assert_eq!(device.join().unwrap(), [START_SYSEX, PIN_STATE_QUERY, 1, END_SYSEX]);
+        let board = conn.board().unwrap();
+        assert_eq!(board.firmware.as_ref().unwrap().name, "Test");
+        assert_eq!(board.pin_count(), 2);
+        assert_eq!(board.pins[&1].mode, Some(PinMode::AnalogInput));
+    }
+
+    #[test]
+    fn digital_write_falls_back_to_port_write() {
+        let mut conn = Connection::new(io::Cursor::new(Vec::new())).unwrap();
+        conn.digital_port_write(1, 0x01).unwrap();
+        conn.digital_write(10, true).unwrap();
+        conn.board_mut().unwrap().protocol = Some(::board::Protocol(2, 5));
+        conn.digital_write(10, false).unwrap();
+        assert_eq!(conn.board().unwrap().digital_read(8), None);
+        assert_eq!(conn.board().unwrap().port_state(1).unwrap().written, Some(0x01));
+
+        match conn {
+            Connection::Open { inner, .. } => assert_eq!(
+                inner.into_inner(),
+                [0x91, 0x01, 0x00, 0x91, 0x05, 0x00, DIGITAL_WRITE, 10, 0]
+            ),
+            Connection::Closed => unreachable!(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn builder_configures_port_and_keeps_dtr_on_close() {
+        use libc;
+        use std::os::unix::io::AsRawFd;
+        let pty = ::pty::Pty::open().unwrap();
+        let conn = ConnectionBuilder::new()
+            .baud_rate(115200)
+            .open(pty.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(conn.baud_rate(), Some(115200));
+
+        let mut termios: libc::termios = unsafe { ::std::mem::zeroed() };
+        match conn {
+            Connection::Open { ref inner, .. } => unsafe {
+                assert_eq!(libc::tcgetattr(inner.as_raw_fd(), &mut termios), 0);
+            },
+            Connection::Closed => unreachable!(),
+        }
+        assert_eq!(termios.c_cflag & libc::HUPCL, 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_detect_settles_on_the_first_answered_rate() {
+        let board = ::pty::VirtualBoard::spawn(::emulator::Emulator::uno()).unwrap();
+        let conn = ConnectionBuilder::new()
+            .open_detect(board.path().to_str().unwrap(), &[115200, 57600])
+            .unwrap();
+        assert_eq!(conn.baud_rate(), Some(115200));
+        assert!(conn.board().unwrap().firmware.is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn setup_builds_the_board_model() {
+        let board = ::pty::VirtualBoard::spawn(::emulator::Emulator::uno()).unwrap();
+        let mut conn = ConnectionBuilder::new().open(board.path().to_str().unwrap()).unwrap();
+        let report = conn.setup().unwrap();
+        assert!(report.is_complete(), "{:?}", report);
+
+        let board = conn.board().unwrap();
+        assert_eq!(board.protocol, Some(::board::Protocol(2, 5)));
+        assert_eq!(board.firmware.as_ref().unwrap().name, "StandardFirmata.ino");
+        assert_eq!(board.pin_count(), 20);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_detect_waits_at_each_rate() {
+        let pty = ::pty::Pty::open().unwrap();
+        let timeout = time::Duration::from_millis(100);
+        let start = time::Instant::now();
+        let result = ConnectionBuilder::new()
+            .detect_timeout(timeout)
+            .open_detect(pty.path().to_str().unwrap(), &[9600, 57600]);
+
+        match result {
+            Err(Error(ErrorKind::BaudRateNotDetected, _)) => {}
+            other => panic!("Expected no baud rate, got {:?}", other.map(|_| ())),
+        }
+        assert!(start.elapsed() >= timeout * 2);
+        let mut queries = [0; 6];
+        pty.master().read_exact(&mut queries).unwrap();
+        assert_eq!(queries, [START_SYSEX, QUERY_FIRMWARE, END_SYSEX, START_SYSEX, QUERY_FIRMWARE, END_SYSEX]);
+    }
+
+    #[test]
+    fn query_times_out_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        let _stream = listener.accept().unwrap();
+
+        match conn.query_pin_state(3, time::Duration::from_millis(50)) {
+            Err(Error(ErrorKind::ResponseTimeout, _)) => {}
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
 }
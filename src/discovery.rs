@@ -0,0 +1,191 @@
+//! Discovery of Firmata boards attached to the host's serial ports.
+//!
+//! Candidate ports are found by scanning `/dev` for USB serial devices, then
+//! each candidate is opened and resynchronized to find out whether a Firmata
+//! device is listening on it.
+
+use ::board::{Firmware, Protocol};
+use ::connection::{ConnectionBuilder, DEFAULT_QUERY_TIMEOUT};
+use ::errors::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Device name prefixes of the serial ports that Firmata boards show up as.
+const PORT_PREFIXES: &[&str] = &["ttyACM", "ttyUSB"];
+
+/// Stable symlinks to serial devices, named after the USB device.
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+/// USB descriptor details of the device behind a serial port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// A serial port that may have a Firmata board attached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialPortInfo {
+    /// The device node, such as `/dev/ttyACM0`.
+    pub path: PathBuf,
+    /// The `/dev/serial/by-id` symlink pointing at the device, if any.
+    pub by_id: Option<PathBuf>,
+    pub usb: Option<UsbInfo>,
+}
+
+/// A Firmata board found on a serial port.
+#[derive(Debug)]
+pub struct FoundBoard {
+    pub port: SerialPortInfo,
+    pub firmware: Firmware,
+    pub protocol: Option<Protocol>,
+}
+
+impl FoundBoard {
+    /// The USB serial number of the board, if it reports one.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.port.usb.as_ref()
+            .and_then(|usb| usb.serial_number.as_ref())
+            .map(|serial| serial.as_str())
+    }
+}
+
+/// List the serial ports that may have a Firmata board attached, without
+/// opening them.
+pub fn ports() -> Vec<SerialPortInfo> {
+    let by_id = by_id_links();
+
+    let mut paths: Vec<PathBuf> = fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| is_candidate(path))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // Devices that only appear through their by-id link are still candidates.
+    for (_, target) in &by_id {
+        if !paths.contains(target) {
+            paths.push(target.clone());
+        }
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let link = by_id
+                .iter()
+                .find(|(_, target)| *target == path)
+                .map(|(link, _)| link.clone());
+            let usb = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(usb_info);
+            SerialPortInfo { path, by_id: link, usb }
+        })
+        .collect()
+}
+
+/// Find every Firmata board attached to the host using the default serial settings.
+pub fn discover() -> Vec<FoundBoard> {
+    discover_with(&ConnectionBuilder::new())
+}
+
+/// Find every Firmata board attached to the host, opening ports with the
+/// given settings. Ports that fail to open or don't answer are skipped.
+pub fn discover_with(builder: &ConnectionBuilder) -> Vec<FoundBoard> {
+    ports()
+        .into_iter()
+        .filter_map(|port| match probe(&port, builder) {
+            Ok(found) => Some(found),
+            Err(e) => {
+                debug!("No Firmata device found on {}: {}", port.path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Open a serial port and ask the Firmata device on it to identify itself.
+///
+/// The board model is reset by every protocol version report, so the
+/// answers are taken from the responses rather than from the board.
+pub fn probe(port: &SerialPortInfo, builder: &ConnectionBuilder) -> Result<FoundBoard> {
+    let mut conn = builder.open(&port.path.to_string_lossy())?;
+    conn.resync()?;
+    let protocol = match conn.query_protocol_version(DEFAULT_QUERY_TIMEOUT) {
+        Ok(protocol) => Some(protocol),
+        Err(Error(ErrorKind::ResponseTimeout, _)) => None,
+        Err(e) => return Err(e),
+    };
+    let firmware = conn.query_firmware_info(DEFAULT_QUERY_TIMEOUT)?;
+    Ok(FoundBoard { port: port.clone(), firmware, protocol })
+}
+
+fn is_candidate(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| PORT_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+}
+
+/// Resolve the `/dev/serial/by-id` symlinks to (link, device) pairs.
+fn by_id_links() -> Vec<(PathBuf, PathBuf)> {
+    fs::read_dir(BY_ID_DIR)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let link = entry.path();
+                    fs::canonicalize(&link).ok().map(|target| (link, target))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Read the USB descriptor of a tty from sysfs.
+///
+/// `/sys/class/tty/<name>/device` points at the USB interface (or, for
+/// USB-serial converters, a child of it); the device directory holding the
+/// descriptor attributes is the nearest ancestor with an `idVendor` file.
+fn usb_info(name: &str) -> Option<UsbInfo> {
+    let device = fs::canonicalize(Path::new("/sys/class/tty").join(name).join("device")).ok()?;
+    let dir = device.ancestors().find(|dir| dir.join("idVendor").is_file())?;
+
+    Some(UsbInfo {
+        vendor_id: read_hex(&dir.join("idVendor"))?,
+        product_id: read_hex(&dir.join("idProduct"))?,
+        serial_number: read_attr(&dir.join("serial")),
+        manufacturer: read_attr(&dir.join("manufacturer")),
+        product: read_attr(&dir.join("product")),
+    })
+}
+
+fn read_attr(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn read_hex(path: &Path) -> Option<u16> {
+    read_attr(path).and_then(|s| u16::from_str_radix(&s, 16).ok())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use emulator::Emulator;
+    use pty::VirtualBoard;
+
+    #[test]
+    fn probe_identifies_virtual_board() {
+        let board = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let port = SerialPortInfo { path: board.path().into(), by_id: None, usb: None };
+        let found = probe(&port, &ConnectionBuilder::new()).unwrap();
+        assert_eq!(found.firmware.name, "StandardFirmata.ino");
+        assert_eq!(found.protocol, Some(Protocol(2, 5)));
+    }
+}
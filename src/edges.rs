@@ -0,0 +1,130 @@
+//! Edge detection for digital inputs such as buttons and limit switches.
+//!
+//! Digital port reports only carry levels. A `Detector` turns the levels of
+//! one pin into rising and falling edges, optionally ignoring contact bounce.
+
+use std::time::{Duration, Instant};
+
+/// A change in the level of a digital pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// The edges a watched pin reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Trigger {
+    pub fn matches(self, edge: Edge) -> bool {
+        matches!(
+            (self, edge),
+            (Trigger::Both, _) | (Trigger::Rising, Edge::Rising) | (Trigger::Falling, Edge::Falling)
+        )
+    }
+}
+
+/// Detects the edges of a single pin.
+///
+/// An edge is reported at the first change of level. With a debounce
+/// window, further changes within the window are treated as bounce. If
+/// the pin has settled at a different level when the window ends, `poll`
+/// reports the edge to it, since the device won't report the level again
+/// until it changes.
+#[derive(Debug, Clone)]
+pub struct Detector {
+    trigger: Trigger,
+    debounce: Option<Duration>,
+    /// The level last reported by the device.
+    raw: Option<bool>,
+    /// The level as of the last edge.
+    level: Option<bool>,
+    last_edge: Option<Instant>,
+}
+
+impl Detector {
+    pub fn new(trigger: Trigger, debounce: Option<Duration>) -> Self {
+        Detector { trigger, debounce, raw: None, level: None, last_edge: None }
+    }
+
+    /// Feed the level of the pin reported at `now`, returning the edge it
+    /// completes if it is one the detector was asked for.
+    ///
+    /// The first level seen only establishes the starting point.
+    pub fn feed(&mut self, level: bool, now: Instant) -> Option<Edge> {
+        self.raw = Some(level);
+        if self.level.is_none() {
+            self.level = Some(level);
+            return None;
+        }
+        let edge = self.poll(now);
+        if self.level != self.raw {
+            trace!("Holding level {} until the debounce window ends", level);
+        }
+        edge
+    }
+
+    /// Check at `now` whether the pin settled at a new level within the
+    /// debounce window, returning the edge to it once the window has ended.
+    pub fn poll(&mut self, now: Instant) -> Option<Edge> {
+        let level = match (self.raw, self.level) {
+            (Some(raw), Some(level)) if raw != level => raw,
+            _ => return None,
+        };
+        let bouncing = match (self.debounce, self.last_edge) {
+            (Some(window), Some(last)) => now.saturating_duration_since(last) < window,
+            _ => false,
+        };
+        if bouncing {
+            return None;
+        }
+
+        self.level = Some(level);
+        self.last_edge = Some(now);
+        let edge = if level { Edge::Rising } else { Edge::Falling };
+        if self.trigger.matches(edge) {
+            Some(edge)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_changes_within_debounce_window() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = Detector::new(Trigger::Both, Some(Duration::from_millis(10)));
+
+        assert_eq!(detector.feed(false, at(0)), None);
+        assert_eq!(detector.feed(true, at(1)), Some(Edge::Rising));
+        assert_eq!(detector.feed(false, at(2)), None);
+        assert_eq!(detector.feed(true, at(3)), None);
+        assert_eq!(detector.feed(false, at(12)), Some(Edge::Falling));
+    }
+
+    #[test]
+    fn reports_changes_that_settle_within_debounce_window() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut detector = Detector::new(Trigger::Both, Some(Duration::from_millis(20)));
+
+        assert_eq!(detector.feed(false, at(0)), None);
+        assert_eq!(detector.feed(true, at(100)), Some(Edge::Rising));
+        // Released before the window ends, and not reported again.
+        assert_eq!(detector.feed(false, at(105)), None);
+        assert_eq!(detector.poll(at(110)), None);
+        assert_eq!(detector.poll(at(120)), Some(Edge::Falling));
+        assert_eq!(detector.poll(at(130)), None);
+        assert_eq!(detector.feed(true, at(200)), Some(Edge::Rising));
+    }
+}
@@ -0,0 +1,302 @@
+//! An in-process Firmata device for testing without hardware.
+//!
+//! An `Emulator` behaves like a serial port with StandardFirmata on the
+//! other end: commands written to it are handled by a `server::Server`
+//! driving simulated hardware described by a `Profile`, and reads return
+//! the queued responses and reports. Reads with nothing queued fail with
+//! `io::ErrorKind::TimedOut`, as a serial port's do.
+//!
+//! Clones share the same device, so a test can hand one clone to a
+//! `Connection` and use another to change input levels.
+
+use ::board::{Firmware, Protocol};
+use ::protocol::*;
+use ::server::{Hardware, Server};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// The pins and identity of an emulated board.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub firmware: Firmware,
+    pub protocol: Protocol,
+    /// The modes each pin supports, indexed by pin number.
+    pub capabilities: Vec<Vec<PinCapability>>,
+    /// The analog channel of each pin, indexed by pin number.
+    pub analog_mapping: Vec<Option<u8>>,
+}
+
+impl Profile {
+    /// An Arduino Uno running StandardFirmata 2.5.
+    pub fn uno() -> Self {
+        let cap = |mode, res| PinCapability { mode, res };
+        let capabilities = (0..20)
+            .map(|pin| {
+                if pin < 2 {
+                    // The serial pins are reserved for the Firmata link.
+                    return Vec::new();
+                }
+                let mut modes = vec![
+                    cap(PinMode::DigitalInput, 1),
+                    cap(PinMode::DigitalOutput, 1),
+                    cap(PinMode::PullUp, 1),
+                ];
+                if [3, 5, 6, 9, 10, 11].contains(&pin) {
+                    modes.push(cap(PinMode::PWM, 8));
+                }
+                if pin < 14 {
+                    modes.push(cap(PinMode::Servo, 14));
+                } else {
+                    modes.push(cap(PinMode::AnalogInput, 10));
+                }
+                if pin == 18 || pin == 19 {
+                    modes.push(cap(PinMode::I2C, 1));
+                }
+                modes
+            })
+            .collect();
+        Profile {
+            firmware: Firmware { major: 2, minor: 5, name: "StandardFirmata.ino".into() },
+            protocol: Protocol(2, 5),
+            capabilities,
+            analog_mapping: (0..20).map(|pin| if pin < 14 { None } else { Some(pin - 14) }).collect(),
+        }
+    }
+}
+
+/// Hardware whose inputs are set by the test.
+#[derive(Debug)]
+struct Simulated {
+    profile: Profile,
+    /// The level or analog value applied to each pin.
+    inputs: Vec<u16>,
+}
+
+impl Hardware for Simulated {
+    fn firmware(&self) -> Firmware {
+        self.profile.firmware.clone()
+    }
+
+    fn protocol(&self) -> Protocol {
+        self.profile.protocol.clone()
+    }
+
+    fn capabilities(&self) -> Vec<Vec<PinCapability>> {
+        self.profile.capabilities.clone()
+    }
+
+    fn analog_mapping(&self) -> Vec<Option<u8>> {
+        self.profile.analog_mapping.clone()
+    }
+
+    fn set_pin_mode(&mut self, _pin: u8, _mode: PinMode) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn digital_write(&mut self, _pin: u8, _level: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn analog_write(&mut self, _pin: u8, _value: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn digital_read(&mut self, pin: u8) -> io::Result<bool> {
+        Ok(self.inputs.get(pin as usize).is_some_and(|&input| input != 0))
+    }
+
+    fn analog_read(&mut self, pin: u8) -> io::Result<u16> {
+        Ok(self.inputs.get(pin as usize).cloned().unwrap_or(0))
+    }
+}
+
+struct Device {
+    server: Server<Simulated>,
+    output: VecDeque<u8>,
+}
+
+impl Device {
+    fn send(&mut self, msg: &FirmataMsg) {
+        self.output.extend(msg.encode());
+    }
+
+    fn set_input(&mut self, pin: u8, value: u16) {
+        if let Some(input) = self.server.hardware_mut().inputs.get_mut(pin as usize) {
+            *input = value;
+        }
+    }
+}
+
+/// A simulated StandardFirmata device.
+#[derive(Clone)]
+pub struct Emulator {
+    inner: Arc<Mutex<Device>>,
+}
+
+impl Emulator {
+    pub fn new(profile: Profile) -> Self {
+        let inputs = vec![0; profile.capabilities.len()];
+        let device = Device {
+            server: Server::new(Simulated { profile, inputs }),
+            output: VecDeque::new(),
+        };
+        Emulator { inner: Arc::new(Mutex::new(device)) }
+    }
+
+    /// Emulate an Arduino Uno.
+    pub fn uno() -> Self {
+        Emulator::new(Profile::uno())
+    }
+
+    /// Queue a message to be read by the host.
+    pub fn send(&self, msg: &FirmataMsg) {
+        self.inner.lock().unwrap().send(msg);
+    }
+
+    /// Apply an analog value to a pin, reporting it if its channel is reported.
+    pub fn set_analog(&self, pin: u8, value: u16) {
+        let mut device = self.inner.lock().unwrap();
+        device.set_input(pin, value);
+        let channel = device.server.hardware().profile.analog_mapping.get(pin as usize).cloned();
+        if let Some(Some(channel)) = channel {
+            if device.server.is_analog_reporting(channel) {
+                device.send(&FirmataMsg::AnalogRead { pin: channel, value });
+            }
+        }
+    }
+
+    /// Apply a digital level to a pin, reporting its port if the pin is a
+    /// reported digital input and its level changed.
+    pub fn set_digital(&self, pin: u8, level: bool) {
+        let mut device = self.inner.lock().unwrap();
+        device.set_input(pin, u16::from(level));
+        for msg in device.server.report_digital() {
+            device.send(&msg);
+        }
+    }
+
+    /// Report every analog channel with reporting enabled, as the firmware
+    /// does once per sampling interval.
+    pub fn sample(&self) {
+        let mut device = self.inner.lock().unwrap();
+        for msg in device.server.report_analog() {
+            device.send(&msg);
+        }
+    }
+
+    /// The mode the host set a pin to.
+    pub fn pin_mode(&self, pin: u8) -> Option<PinMode> {
+        self.inner.lock().unwrap().server.pin_mode(pin)
+    }
+
+    /// The value the host wrote to a pin.
+    pub fn pin_value(&self, pin: u8) -> Option<u32> {
+        self.inner.lock().unwrap().server.pin_value(pin)
+    }
+
+    pub fn is_analog_reporting(&self, channel: u8) -> bool {
+        self.inner.lock().unwrap().server.is_analog_reporting(channel)
+    }
+
+    pub fn is_digital_reporting(&self, port: u8) -> bool {
+        self.inner.lock().unwrap().server.is_digital_reporting(port)
+    }
+}
+
+impl io::Read for Emulator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut device = self.inner.lock().unwrap();
+        if device.output.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Emulated device has nothing to send"));
+        }
+        let len = buf.len().min(device.output.len());
+        for (byte, out) in device.output.drain(..len).zip(buf.iter_mut()) {
+            *out = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl io::Write for Emulator {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut device = self.inner.lock().unwrap();
+        for msg in device.server.receive(buf) {
+            device.send(&msg);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connection::Connection;
+    use edges::{Edge, Trigger};
+    use events::{Event, EventKind, Filter};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use worker::Worker;
+
+    #[test]
+    fn initializes_connection() {
+        let device = Emulator::uno();
+        let mut conn = Connection::new(device.clone()).unwrap();
+        conn.resync().unwrap();
+        let report = conn.initialize_with(Duration::from_millis(50)).unwrap();
+
+        assert!(report.is_complete(), "{:?}", report);
+        let board = conn.board().unwrap();
+        assert_eq!(board.firmware.as_ref().unwrap().name, "StandardFirmata.ino");
+        assert_eq!(board.pin_count(), 20);
+        assert_eq!(board.analog_pin(2), Some(16));
+        assert_eq!(board.pins[&16].mode, Some(PinMode::AnalogInput));
+        assert_eq!(board.pins[&13].mode, Some(PinMode::DigitalOutput));
+    }
+
+    #[test]
+    fn applies_commands_and_reports_inputs() {
+        let device = Emulator::uno();
+        let mut conn = Connection::new(device.clone()).unwrap();
+        conn.set_pin_mode(4, PinMode::PullUp).unwrap();
+        conn.set_pin_mode(13, PinMode::DigitalOutput).unwrap();
+        conn.digital_write(13, true).unwrap();
+        conn.board_mut().unwrap().watch_edges(4, Trigger::Falling, None);
+        conn.digital_report(0, true).unwrap();
+
+        assert_eq!(device.pin_mode(4), Some(PinMode::PullUp));
+        assert_eq!(device.pin_value(13), Some(1));
+        device.set_digital(4, true);
+        device.set_digital(4, false);
+
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.extend(conn.update().unwrap());
+        }
+        assert!(
+            events.iter().any(|e| matches!(*e, Event::Edge { pin: 4, edge: Edge::Falling, .. })),
+            "{:?}",
+            events
+        );
+        assert_eq!(conn.board().unwrap().digital_read(4), Some(false));
+    }
+
+    #[test]
+    fn worker_publishes_reported_values() {
+        let device = Emulator::uno();
+        let conn = Arc::new(Mutex::new(Connection::new(device.clone()).unwrap()));
+        conn.lock().unwrap().analog_report(1, true).unwrap();
+
+        let handle = Worker::new(&conn).interval(Duration::from_millis(1)).start();
+        let changes = handle.subscribe(Filter::new().kind(EventKind::PinChanged));
+        device.set_analog(15, 700);
+
+        let event = changes.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(event, Event::PinChanged { pin: 1, value: 700 });
+        handle.shutdown().unwrap();
+    }
+}
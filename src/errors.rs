@@ -24,5 +24,11 @@ error_chain! {
         ConnectionClosed {
             description("Serial connection to Firmata device closed")
         }
+        ResponseTimeout {
+            description("Timed out waiting for a Firmata response")
+        }
+        BaudRateNotDetected {
+            description("No baud rate produced a valid Firmata response")
+        }
     }
 }
@@ -0,0 +1,156 @@
+//! Typed events describing changes to a board, delivered over channels.
+//!
+//! Rather than locking the connection to poll `Board::pins`, consumers
+//! subscribe to the events they care about and block on a receiver.
+
+use ::board::Firmware;
+use ::edges::Edge;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A pin reported a value different from the last one seen.
+    PinChanged { pin: u8, value: u16 },
+    /// A watched digital input changed level. `at` is when the report
+    /// that completed the edge arrived.
+    Edge { pin: u8, edge: Edge, at: Instant },
+    FirmwareIdentified(Firmware),
+    StringData(String),
+    I2cReply {
+        address: u16,
+        register: u16,
+        data: Vec<u8>,
+    },
+    /// The connection to the device was lost.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    PinChanged,
+    Edge,
+    FirmwareIdentified,
+    StringData,
+    I2cReply,
+    Disconnected,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match *self {
+            Event::PinChanged { .. } => EventKind::PinChanged,
+            Event::Edge { .. } => EventKind::Edge,
+            Event::FirmwareIdentified(_) => EventKind::FirmwareIdentified,
+            Event::StringData(_) => EventKind::StringData,
+            Event::I2cReply { .. } => EventKind::I2cReply,
+            Event::Disconnected => EventKind::Disconnected,
+        }
+    }
+
+    /// The pin this event is about, if it is about a single pin.
+    pub fn pin(&self) -> Option<u8> {
+        match *self {
+            Event::PinChanged { pin, .. } |
+            Event::Edge { pin, .. } => Some(pin),
+            _ => None,
+        }
+    }
+}
+
+/// Selects the events delivered to a subscriber.
+///
+/// An empty filter matches every event. Adding kinds restricts events to
+/// those kinds, and adding pins restricts events to ones about those pins.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    kinds: HashSet<EventKind>,
+    pins: HashSet<u8>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    pub fn kind(mut self, kind: EventKind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    pub fn pin(mut self, pin: u8) -> Self {
+        self.pins.insert(pin);
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let kind_matches = self.kinds.is_empty() || self.kinds.contains(&event.kind());
+        let pin_matches = self.pins.is_empty() || event.pin().is_some_and(|pin| self.pins.contains(&pin));
+        kind_matches && pin_matches
+    }
+}
+
+type Subscriber = (Filter, Sender<Event>);
+
+/// A set of subscribers that events are published to.
+///
+/// Cloning an `Events` shares the subscribers, so the application can keep a
+/// clone to subscribe with while a worker publishes on another thread.
+#[derive(Clone, Default)]
+pub struct Events {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Events::default()
+    }
+
+    /// Receive every future event that matches `filter`.
+    pub fn subscribe(&self, filter: Filter) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push((filter, tx));
+        rx
+    }
+
+    /// Send an event to all matching subscribers, forgetting subscribers
+    /// whose receiver has been dropped.
+    pub fn publish(&self, event: &Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|(filter, tx)| !filter.matches(event) || tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_events_matching_filter() {
+        let events = Events::new();
+        let pin_3 = events.subscribe(Filter::new().pin(3));
+        let strings = events.subscribe(Filter::new().kind(EventKind::StringData));
+        let all = events.subscribe(Filter::new());
+
+        events.publish(&Event::PinChanged { pin: 3, value: 1 });
+        events.publish(&Event::PinChanged { pin: 4, value: 1 });
+        events.publish(&Event::StringData("hello".to_string()));
+
+        assert_eq!(pin_3.try_iter().collect::<Vec<_>>(), vec![Event::PinChanged { pin: 3, value: 1 }]);
+        assert_eq!(strings.try_iter().collect::<Vec<_>>(), vec![Event::StringData("hello".to_string())]);
+        assert_eq!(all.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn forgets_dropped_subscribers() {
+        let events = Events::new();
+        drop(events.subscribe(Filter::new()));
+        events.publish(&Event::Disconnected);
+
+        assert!(events.subscribers.lock().unwrap().is_empty());
+    }
+}
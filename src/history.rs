@@ -0,0 +1,148 @@
+//! A bounded record of the values reported for a pin.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How much history a pin keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Keep the most recent number of samples.
+    Samples(usize),
+    /// Keep the samples reported within this long of the newest one.
+    Age(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub at: Instant,
+    pub value: u16,
+}
+
+/// Summary of the samples in a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: u16,
+    pub max: u16,
+    pub mean: f64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    limit: Limit,
+    samples: VecDeque<Sample>,
+}
+
+impl History {
+    pub fn new(limit: Limit) -> Self {
+        History { limit, samples: VecDeque::new() }
+    }
+
+    pub fn limit(&self) -> Limit {
+        self.limit
+    }
+
+    /// Record a value, dropping the samples that fall outside the limit.
+    pub fn push(&mut self, value: u16, at: Instant) {
+        self.samples.push_back(Sample { at, value });
+        match self.limit {
+            Limit::Samples(count) => {
+                while self.samples.len() > count {
+                    self.samples.pop_front();
+                }
+            }
+            Limit::Age(age) => {
+                while self.samples.front().is_some_and(|s| at.saturating_duration_since(s.at) > age) {
+                    self.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Every sample kept, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// The samples taken at or after `start`, oldest first.
+    pub fn since(&self, start: Instant) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().skip_while(move |s| s.at < start)
+    }
+
+    /// Summarize the samples taken at or after `start`.
+    pub fn stats_since(&self, start: Instant) -> Option<Stats> {
+        let mut samples = self.since(start);
+        let first = samples.next()?.value;
+        let mut stats = Stats { min: first, max: first, mean: 0.0, count: 1 };
+        let mut sum = u64::from(first);
+        for sample in samples {
+            stats.min = stats.min.min(sample.value);
+            stats.max = stats.max.max(sample.value);
+            stats.count += 1;
+            sum += u64::from(sample.value);
+        }
+        stats.mean = sum as f64 / stats.count as f64;
+        Some(stats)
+    }
+
+    /// Summarize the samples taken within the last `window`.
+    pub fn stats(&self, window: Duration) -> Option<Stats> {
+        let now = Instant::now();
+        self.stats_since(now.checked_sub(window).unwrap_or(now))
+    }
+
+    pub fn min(&self, window: Duration) -> Option<u16> {
+        self.stats(window).map(|stats| stats.min)
+    }
+
+    pub fn max(&self, window: Duration) -> Option<u16> {
+        self.stats(window).map(|stats| stats.max)
+    }
+
+    pub fn mean(&self, window: Duration) -> Option<f64> {
+        self.stats(window).map(|stats| stats.mean)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_samples_within_limit() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut by_count = History::new(Limit::Samples(2));
+        let mut by_age = History::new(Limit::Age(Duration::from_millis(100)));
+        for &(ms, value) in &[(0, 10), (50, 30), (120, 20)] {
+            by_count.push(value, at(ms));
+            by_age.push(value, at(ms));
+        }
+
+        assert_eq!(by_count.samples().map(|s| s.value).collect::<Vec<_>>(), vec![30, 20]);
+        assert_eq!(by_age.samples().map(|s| s.value).collect::<Vec<_>>(), vec![30, 20]);
+        assert_eq!(
+            by_age.stats_since(at(0)),
+            Some(Stats { min: 20, max: 30, mean: 25.0, count: 2 })
+        );
+        assert_eq!(by_age.stats_since(at(100)).map(|s| s.count), Some(1));
+        assert_eq!(by_age.stats_since(at(200)), None);
+    }
+}
@@ -1,9 +1,15 @@
 #[macro_use] extern crate nom;
 #[macro_use] extern crate error_chain;
 #[macro_use] extern crate log;
+extern crate libc;
 extern crate serial_core;
 extern crate serial_unix;
 
+#[cfg(feature = "tokio")] extern crate bytes;
+#[cfg(feature = "tokio")] extern crate futures;
+#[cfg(feature = "tokio")] extern crate tokio;
+#[cfg(feature = "tokio")] extern crate tokio_util;
+
 pub use self::protocol::*;
 pub mod protocol;
 
@@ -11,8 +17,26 @@ pub use self::parser::*;
 pub mod parser;
 
 pub mod client;
+#[cfg(feature = "tokio")]
+pub mod codec;
+#[cfg(feature = "tokio")]
+pub mod async_connection;
 pub mod connection;
+pub mod discovery;
+pub mod edges;
+pub mod emulator;
 pub mod errors;
+pub mod events;
+pub mod history;
+pub mod mock;
+#[cfg(target_os = "linux")]
+pub mod pty;
+pub mod reconnect;
+pub mod server;
+pub mod session;
+pub mod sniffer;
+pub mod split;
+pub mod tcp;
 pub mod worker;
 
 pub use self::board::*;
@@ -0,0 +1,239 @@
+//! A scripted transport for testing error handling.
+//!
+//! A `MockPort` plays back a script of reads, one step per call to `read`:
+//! chunks of bytes, timeouts, I/O errors and end of stream. Everything the
+//! host writes is recorded so tests can check the bytes of each command.
+
+use std::collections::VecDeque;
+use std::io;
+
+/// The outcome of one read from a `MockPort`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Bytes returned by reads. A chunk longer than the read buffer is
+    /// returned over several reads.
+    Data(Vec<u8>),
+    /// A read that fails with `io::ErrorKind::TimedOut`.
+    Timeout,
+    /// A read that fails with an error of the given kind.
+    Error(io::ErrorKind),
+    /// A read that returns 0, as a closed stream does.
+    Eof,
+}
+
+#[derive(Debug, Default)]
+pub struct MockPort {
+    script: VecDeque<Step>,
+    written: Vec<u8>,
+    write_error: Option<io::ErrorKind>,
+}
+
+impl MockPort {
+    /// A port with an empty script. Reads past the end of the script time
+    /// out, like a serial port attached to an idle device.
+    pub fn new() -> Self {
+        MockPort::default()
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.script.push_back(step);
+        self
+    }
+
+    pub fn data(self, bytes: &[u8]) -> Self {
+        self.step(Step::Data(bytes.to_vec()))
+    }
+
+    pub fn timeout(self) -> Self {
+        self.step(Step::Timeout)
+    }
+
+    pub fn error(self, kind: io::ErrorKind) -> Self {
+        self.step(Step::Error(kind))
+    }
+
+    pub fn eof(self) -> Self {
+        self.step(Step::Eof)
+    }
+
+    /// Make every write fail with an error of the given kind.
+    pub fn fail_writes(mut self, kind: io::ErrorKind) -> Self {
+        self.write_error = Some(kind);
+        self
+    }
+
+    /// The bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Take the bytes written so far, so the next check starts fresh.
+    pub fn take_written(&mut self) -> Vec<u8> {
+        ::std::mem::take(&mut self.written)
+    }
+
+    /// The steps that haven't been played yet.
+    pub fn remaining(&self) -> usize {
+        self.script.len()
+    }
+}
+
+impl io::Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.script.pop_front() {
+            Some(Step::Data(mut bytes)) => {
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                if len < bytes.len() {
+                    self.script.push_front(Step::Data(bytes.split_off(len)));
+                }
+                Ok(len)
+            }
+            Some(Step::Timeout) | None => Err(io::Error::new(io::ErrorKind::TimedOut, "Scripted timeout")),
+            Some(Step::Error(kind)) => Err(io::Error::new(kind, "Scripted error")),
+            Some(Step::Eof) => Ok(0),
+        }
+    }
+}
+
+impl io::Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.write_error {
+            Some(kind) => Err(io::Error::new(kind, "Scripted write error")),
+            None => {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client;
+    use errors::*;
+    use protocol::*;
+
+    fn io_kind<T: ::std::fmt::Debug>(result: Result<T>) -> io::ErrorKind {
+        match result {
+            Err(Error(ErrorKind::Io(e), _)) => e.kind(),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn commands_send_expected_bytes() {
+        type Command = fn(&mut MockPort) -> io::Result<()>;
+        let commands: Vec<(Command, &[u8])> = vec![
+            (client::reset, &[RESET]),
+            (client::query_firmware, &[START_SYSEX, QUERY_FIRMWARE, END_SYSEX]),
+            (client::query_version, &[PROTOCOL_VERSION]),
+            (client::capabilities, &[START_SYSEX, CAPABILITY_QUERY, END_SYSEX]),
+            (client::analog_mapping, &[START_SYSEX, ANALOG_MAPPING_QUERY, END_SYSEX]),
+            (|c| client::pin_state(c, 7), &[START_SYSEX, PIN_STATE_QUERY, 7, END_SYSEX]),
+            (|c| client::set_pin_mode(c, 3, PinMode::PWM), &[SET_PIN_MODE, 3, 0x03]),
+            (|c| client::analog_report(c, 2, true), &[0xC2, 1]),
+            (|c| client::digital_report(c, 1, false), &[0xD1, 0]),
+            (|c| client::digital_write(c, 13, true), &[DIGITAL_WRITE, 13, 1]),
+            (|c| client::digital_port_write(c, 2, 0x81), &[0x92, 0x01, 0x01]),
+            (|c| client::analog_write(c, 9, 1000), &[0xE9, 0x68, 0x07]),
+            (|c| client::analog_write(c, 20, 1000), &[START_SYSEX, EXTENDED_ANALOG, 20, 0x68, 0x07, END_SYSEX]),
+            (|c| client::i2c_config(c, 200), &[START_SYSEX, I2C_CONFIG, 0x48, 0x01, END_SYSEX]),
+            (|c| client::i2c_write(c, 0x68, &[0x6B, 0x80]), &[START_SYSEX, I2C_REQUEST, 0x68, 0x00, 0x6B, 0, 0x00, 1, END_SYSEX]),
+            (|c| client::i2c_read(c, 0x68, Some(0x3B), 6), &[START_SYSEX, I2C_REQUEST, 0x68, 0x08, 0x3B, 0, 6, 0, END_SYSEX]),
+            (|c| client::i2c_read(c, 0x50, None, 2), &[START_SYSEX, I2C_REQUEST, 0x50, 0x08, 2, 0, END_SYSEX]),
+        ];
+
+        for (command, expected) in commands {
+            let mut port = MockPort::new();
+            command(&mut port).unwrap();
+            assert_eq!(port.written(), expected);
+
+            let mut broken = MockPort::new().fail_writes(io::ErrorKind::BrokenPipe);
+            assert_eq!(command(&mut broken).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        }
+    }
+
+    #[test]
+    fn commands_reject_out_of_range_indices() {
+        let mut port = MockPort::new();
+        let results = vec![
+            client::analog_report(&mut port, 16, true),
+            client::digital_report(&mut port, 16, true),
+            client::digital_port_write(&mut port, 16, 0),
+            client::i2c_read(&mut port, 0x80, None, 1),
+        ];
+
+        for result in results {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(port.written().is_empty());
+    }
+
+    #[test]
+    fn read_assembles_messages_across_chunks_and_timeouts() {
+        let mut port = MockPort::new()
+            .data(b"\xF9")
+            .timeout()
+            .data(b"\x02")
+            .timeout()
+            .timeout()
+            .data(b"\x06");
+
+        assert_eq!(
+            client::read(&mut port).unwrap(),
+            FirmataMsg::ProtocolVersion { major: 2, minor: 6 }
+        );
+        assert_eq!(port.remaining(), 0);
+    }
+
+    #[test]
+    fn read_gives_up_after_retrying_timeouts() {
+        let mut port = MockPort::new().timeout().timeout().timeout().timeout().data(b"\xF9\x02\x06");
+
+        assert_eq!(io_kind(client::read(&mut port)), io::ErrorKind::TimedOut);
+        assert!(client::read(&mut port).is_ok());
+    }
+
+    #[test]
+    fn read_reports_errors() {
+        let mut port = MockPort::new()
+            .data(b"\x85")
+            .error(io::ErrorKind::BrokenPipe)
+            .data(b"\xF9\x02")
+            .eof();
+
+        match client::read(&mut port) {
+            Err(Error(ErrorKind::UnreadableMsg, _)) => {}
+            other => panic!("Expected an unreadable message, got {:?}", other),
+        }
+        assert_eq!(io_kind(client::read(&mut port)), io::ErrorKind::BrokenPipe);
+        assert_eq!(io_kind(client::read(&mut port)), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn resync_discards_stale_input() {
+        let mut port = MockPort::new()
+            .data(b"\x12\x34")
+            .data(&FirmataMsg::AnalogRead { pin: 0, value: 1 }.encode())
+            .data(b"\xF9\x02\x05");
+
+        let msg = client::resync(&mut port).unwrap();
+        assert_eq!(msg, FirmataMsg::ProtocolVersion { major: 2, minor: 5 });
+        assert_eq!(port.written(), [RESET, START_SYSEX, QUERY_FIRMWARE, END_SYSEX]);
+    }
+
+    #[test]
+    fn resync_gives_up_after_five_attempts() {
+        let mut port = MockPort::new();
+
+        let err = client::resync(&mut port).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(port.written(), [RESET, START_SYSEX, QUERY_FIRMWARE, END_SYSEX].repeat(5).as_slice());
+    }
+}
@@ -62,6 +62,21 @@ named!(analog_read<&[u8], FirmataMsg>,
        )
 );
 
+named!(digital_read<&[u8], FirmataMsg>,
+       bits!(
+           do_parse!(
+               tag_bits!(u8, 4, DIGITAL_MESSAGE) >>
+               port: take_bits!(u8, 4)           >>
+               lsb: take_bits!(u8, 8)            >>
+               msb: take_bits!(u8, 8)            >>
+               (FirmataMsg::DigitalRead {
+                       port,
+                       value: (msb << 7) | (lsb & 0x7F)
+               })
+           )
+       )
+);
+
 named!(protocol_version<&[u8], FirmataMsg>,
        do_parse!(
            tag!(&[PROTOCOL_VERSION]) >>
@@ -74,13 +89,80 @@ named!(protocol_version<&[u8], FirmataMsg>,
        )
 );
 
+/// Decode sysex data sent as pairs of 7 bit bytes, least significant first.
+fn decode_pairs(data: &[u8]) -> Vec<u8> {
+    data.chunks(2)
+        .map(|pair| pair[0] | pair.get(1).map_or(0, |msb| msb << 7))
+        .collect()
+}
+
+named!(string_data<&[u8], FirmataMsg>,
+       do_parse!(
+           tag!(&[STRING_DATA])                        >>
+           data: take_while!(|chr: u8| chr < 0x80)     >>
+           (FirmataMsg::StringData(
+               String::from_utf8_lossy(&decode_pairs(data)).into_owned()
+           ))
+       )
+);
+
+named!(i2c_reply<&[u8], FirmataMsg>,
+       do_parse!(
+           tag!(&[I2C_REPLY])                          >>
+           address: take!(2)                           >>
+           register: take!(2)                          >>
+           data: take_while!(|chr: u8| chr < 0x80)     >>
+           (FirmataMsg::I2cReply {
+               address: u16::from(address[0]) | u16::from(address[1]) << 7,
+               register: u16::from(register[0]) | u16::from(register[1]) << 7,
+               data: decode_pairs(data)
+           })
+       )
+);
+
+named!(analog_mapping_response<&[u8], FirmataMsg>,
+       do_parse!(
+           tag!(&[ANALOG_MAPPING_RESPONSE])            >>
+           channels: take_while!(|chr: u8| chr < 0x80) >>
+           (FirmataMsg::AnalogMappingResponse(
+               channels
+                   .iter()
+                   .map(|&chr| if chr == ANALOG_MAPPING_NONE { None } else { Some(chr) })
+                   .collect()
+           ))
+       )
+);
+
+named!(pin_state_response<&[u8], FirmataMsg>,
+       do_parse!(
+           tag!(&[PIN_STATE_RESPONSE])                 >>
+           pin: take!(1)                               >>
+           mode: take!(1)                              >>
+           state: take_while!(|chr: u8| chr < 0x80)    >>
+           (FirmataMsg::PinStateResponse(PinState {
+               pin: pin[0],
+               mode: PinMode::from(mode[0]),
+               state: state
+                   .iter()
+                   .enumerate()
+                   .fold(0, |acc, (i, &chr)| {
+                       acc | u32::from(chr).checked_shl(7 * i as u32).unwrap_or(0)
+                   })
+           }))
+       )
+);
+
 named!(sysex<&[u8], FirmataMsg>,
        delimited!(
            tag!(&[START_SYSEX]),
            alt!(
                query_firmware      |
                capability_query    |
-               capability_response
+               capability_response |
+               analog_mapping_response |
+               pin_state_response  |
+               string_data         |
+               i2c_reply
            ),
            tag!(&[END_SYSEX])
        )
@@ -89,11 +171,186 @@ named!(sysex<&[u8], FirmataMsg>,
 named!(pub parse<&[u8], FirmataMsg>,
        alt!(
            sysex       |
-           analog_read |
+           analog_read  |
+           digital_read |
            protocol_version
         )
 );
 
+// Commands sent from the host to the device.
+
+/// The nybbles of the report toggles, which are matched as patterns.
+const ANALOG_REPORT_NYBBLE: u8 = ANALOG_REPORT >> 4;
+const DIGITAL_REPORT_NYBBLE: u8 = DIGITAL_REPORT >> 4;
+
+named!(host_query_firmware<&[u8], HostCommand>,
+       map!(tag!(&[QUERY_FIRMWARE]), |_| HostCommand::QueryFirmware));
+
+named!(host_capability_query<&[u8], HostCommand>,
+       map!(tag!(&[CAPABILITY_QUERY]), |_| HostCommand::CapabilityQuery));
+
+named!(host_analog_mapping_query<&[u8], HostCommand>,
+       map!(tag!(&[ANALOG_MAPPING_QUERY]), |_| HostCommand::AnalogMappingQuery));
+
+named!(host_pin_state_query<&[u8], HostCommand>,
+       do_parse!(
+           tag!(&[PIN_STATE_QUERY]) >>
+           pin: take!(1)            >>
+           (HostCommand::PinStateQuery(pin[0]))
+       )
+);
+
+named!(host_extended_analog<&[u8], HostCommand>,
+       do_parse!(
+           tag!(&[EXTENDED_ANALOG])                    >>
+           pin: take!(1)                               >>
+           value: take_while!(|chr: u8| chr < 0x80)    >>
+           (HostCommand::AnalogWrite {
+               pin: pin[0],
+               value: value
+                   .iter()
+                   .enumerate()
+                   .fold(0, |acc, (i, &chr)| {
+                       acc | u32::from(chr).checked_shl(7 * i as u32).unwrap_or(0)
+                   })
+           })
+       )
+);
+
+named!(host_sampling_interval<&[u8], HostCommand>,
+       do_parse!(
+           tag!(&[SAMPLING_INTERVAL]) >>
+           interval: take!(2)         >>
+           (HostCommand::SamplingInterval(u16::from(interval[0]) | u16::from(interval[1]) << 7))
+       )
+);
+
+named!(host_sysex_other<&[u8], HostCommand>,
+       map!(take_while!(|chr: u8| chr < 0x80), |data: &[u8]| HostCommand::Sysex(data.to_vec())));
+
+named!(host_sysex<&[u8], HostCommand>,
+       delimited!(
+           tag!(&[START_SYSEX]),
+           alt!(
+               host_query_firmware       |
+               host_capability_query     |
+               host_analog_mapping_query |
+               host_pin_state_query      |
+               host_extended_analog      |
+               host_sampling_interval    |
+               host_sysex_other
+           ),
+           tag!(&[END_SYSEX])
+       )
+);
+
+named!(host_reset<&[u8], HostCommand>,
+       map!(tag!(&[RESET]), |_| HostCommand::Reset));
+
+named!(host_query_version<&[u8], HostCommand>,
+       map!(tag!(&[PROTOCOL_VERSION]), |_| HostCommand::QueryVersion));
+
+named!(host_set_pin_mode<&[u8], HostCommand>,
+       do_parse!(
+           tag!(&[SET_PIN_MODE]) >>
+           pin: take!(1)         >>
+           mode: take!(1)        >>
+           (HostCommand::SetPinMode { pin: pin[0], mode: PinMode::from(mode[0]) })
+       )
+);
+
+named!(host_digital_write<&[u8], HostCommand>,
+       do_parse!(
+           tag!(&[DIGITAL_WRITE]) >>
+           pin: take!(1)          >>
+           level: take!(1)        >>
+           (HostCommand::DigitalWrite { pin: pin[0], level: level[0] != 0 })
+       )
+);
+
+named!(host_digital_port_write<&[u8], HostCommand>,
+       bits!(
+           do_parse!(
+               tag_bits!(u8, 4, DIGITAL_MESSAGE) >>
+               port: take_bits!(u8, 4)           >>
+               lsb: take_bits!(u8, 8)            >>
+               msb: take_bits!(u8, 8)            >>
+               (HostCommand::DigitalPortWrite { port, value: (msb << 7) | (lsb & 0x7F) })
+           )
+       )
+);
+
+named!(host_analog_write<&[u8], HostCommand>,
+       bits!(
+           do_parse!(
+               tag_bits!(u8, 4, ANALOG_MESSAGE) >>
+               pin: take_bits!(u8, 4)           >>
+               lsb: take_bits!(u8, 8)           >>
+               msb: take_bits!(u8, 8)           >>
+               (HostCommand::AnalogWrite { pin, value: u32::from(msb) << 7 | u32::from(lsb) })
+           )
+       )
+);
+
+named!(host_analog_report<&[u8], HostCommand>,
+       bits!(
+           do_parse!(
+               tag_bits!(u8, 4, ANALOG_REPORT_NYBBLE) >>
+               channel: take_bits!(u8, 4)           >>
+               enabled: take_bits!(u8, 8)           >>
+               (HostCommand::AnalogReport { channel, enabled: enabled != 0 })
+           )
+       )
+);
+
+named!(host_digital_report<&[u8], HostCommand>,
+       bits!(
+           do_parse!(
+               tag_bits!(u8, 4, DIGITAL_REPORT_NYBBLE) >>
+               port: take_bits!(u8, 4)               >>
+               enabled: take_bits!(u8, 8)            >>
+               (HostCommand::DigitalReport { port, enabled: enabled != 0 })
+           )
+       )
+);
+
+// Parse a command sent by the host, the other direction from `parse`.
+named!(pub parse_command<&[u8], HostCommand>,
+       alt!(
+           host_reset              |
+           host_query_version      |
+           host_set_pin_mode       |
+           host_digital_write      |
+           host_sysex              |
+           host_digital_port_write |
+           host_analog_write       |
+           host_analog_report      |
+           host_digital_report
+        )
+);
+
+/// Which end of a link sent a stream of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    HostToDevice,
+    DeviceToHost,
+}
+
+/// A message decoded from either direction of a link.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Host(HostCommand),
+    Device(FirmataMsg),
+}
+
+/// Parse a message sent in `direction`.
+pub fn parse_from(direction: Direction, input: &[u8]) -> nom::IResult<&[u8], Message> {
+    match direction {
+        Direction::HostToDevice => parse_command(input).map(|(rest, cmd)| (rest, Message::Host(cmd))),
+        Direction::DeviceToHost => parse(input).map(|(rest, msg)| (rest, Message::Device(msg))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,6 +558,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_sysex_analog_mapping_response() {
+        let msg = [START_SYSEX, ANALOG_MAPPING_RESPONSE, 0x7F, 0x7F, 0x00, 0x01, END_SYSEX];
+
+        assert_eq!(
+            sysex(&msg[..]),
+            Ok((
+                EMPTY,
+                FirmataMsg::AnalogMappingResponse(vec![None, None, Some(0), Some(1)])
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_sysex_pin_state_response() {
+        let msg = [START_SYSEX, PIN_STATE_RESPONSE, 0x09, 0x03, 0x7F, 0x01, END_SYSEX];
+
+        assert_eq!(
+            sysex(&msg[..]),
+            Ok((
+                EMPTY,
+                FirmataMsg::PinStateResponse(PinState {
+                    pin: 9,
+                    mode: PinMode::PWM,
+                    state: 255,
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_sysex_string_data() {
+        let msg = b"\xF0\x71h\x00i\x00\xF7";
+
+        assert_eq!(
+            sysex(&msg[..]),
+            Ok((EMPTY, FirmataMsg::StringData("hi".to_string())))
+        );
+    }
+
+    #[test]
+    fn parses_sysex_i2c_reply() {
+        let msg = [
+            START_SYSEX,
+            I2C_REPLY,
+            0x68,
+            0x00,
+            0x3B,
+            0x00,
+            0x7F,
+            0x01,
+            0x05,
+            0x00,
+            END_SYSEX,
+        ];
+
+        assert_eq!(
+            sysex(&msg[..]),
+            Ok((
+                EMPTY,
+                FirmataMsg::I2cReply {
+                    address: 0x68,
+                    register: 0x3B,
+                    data: vec![0xFF, 0x05],
+                }
+            ))
+        );
+    }
+
     #[test]
     fn parses_analog_read() {
         let pin = 15;
@@ -317,6 +643,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_digital_read() {
+        let msg: [u8; 3] = [DIGITAL_MESSAGE << 4 | 2, 0x05, 0x01];
+
+        assert_eq!(
+            parse(&msg[..]),
+            Ok((EMPTY, FirmataMsg::DigitalRead { port: 2, value: 0x85 }))
+        );
+    }
+
     #[test]
     fn parses_protocol_version() {
         assert_eq!(
@@ -324,4 +660,93 @@ mod tests {
             Ok((EMPTY, FirmataMsg::ProtocolVersion { major: 2, minor: 6 }))
         );
     }
+
+    #[test]
+    fn parses_client_commands() {
+        let mut buf = Vec::new();
+        ::client::set_pin_mode(&mut buf, 3, PinMode::PWM).unwrap();
+        ::client::analog_write(&mut buf, 3, 200).unwrap();
+        ::client::analog_report(&mut buf, 1, true).unwrap();
+        ::client::pin_state(&mut buf, 3).unwrap();
+
+        let mut commands = Vec::new();
+        let mut input = &buf[..];
+        while !input.is_empty() {
+            let (rest, cmd) = parse_command(input).unwrap();
+            commands.push(cmd);
+            input = rest;
+        }
+        assert_eq!(commands, vec![
+            HostCommand::SetPinMode { pin: 3, mode: PinMode::PWM },
+            HostCommand::AnalogWrite { pin: 3, value: 200 },
+            HostCommand::AnalogReport { channel: 1, enabled: true },
+            HostCommand::PinStateQuery(3),
+        ]);
+    }
+
+    #[test]
+    fn parses_by_direction() {
+        let bytes = [PROTOCOL_VERSION, 2, 6];
+
+        assert_eq!(
+            parse_from(Direction::HostToDevice, &bytes[..]),
+            Ok((&bytes[1..], Message::Host(HostCommand::QueryVersion)))
+        );
+        assert_eq!(
+            parse_from(Direction::DeviceToHost, &bytes[..]),
+            Ok((EMPTY, Message::Device(FirmataMsg::ProtocolVersion { major: 2, minor: 6 })))
+        );
+    }
+
+    #[test]
+    fn parses_encoded_commands() {
+        let commands = vec![
+            HostCommand::Reset,
+            HostCommand::QueryVersion,
+            HostCommand::QueryFirmware,
+            HostCommand::CapabilityQuery,
+            HostCommand::AnalogMappingQuery,
+            HostCommand::PinStateQuery(9),
+            HostCommand::SetPinMode { pin: 13, mode: PinMode::DigitalOutput },
+            HostCommand::DigitalPortWrite { port: 1, value: 0xA5 },
+            HostCommand::DigitalWrite { pin: 13, level: true },
+            HostCommand::AnalogWrite { pin: 9, value: 255 },
+            HostCommand::AnalogWrite { pin: 20, value: 1 << 16 },
+            HostCommand::AnalogReport { channel: 5, enabled: false },
+            HostCommand::DigitalReport { port: 2, enabled: true },
+            HostCommand::SamplingInterval(1000),
+            HostCommand::Sysex(vec![0x78, 0x01]),
+        ];
+
+        for cmd in commands {
+            assert_eq!(parse_command(&cmd.encode()[..]), Ok((EMPTY, cmd.clone())));
+        }
+    }
+
+    #[test]
+    fn parses_encoded_messages() {
+        let messages = vec![
+            FirmataMsg::QueryFirmware {
+                major: 2,
+                minor: 6,
+                firmware_name: b"StandardFirmata.ino".to_vec(),
+            },
+            FirmataMsg::CapabilityResponse(vec![
+                vec![PinCapability { mode: PinMode::DigitalInput, res: 1 }],
+                vec![],
+            ]),
+            FirmataMsg::AnalogRead { pin: 3, value: 1023 },
+            FirmataMsg::DigitalRead { port: 1, value: 0xFF },
+            FirmataMsg::ProtocolVersion { major: 2, minor: 6 },
+            FirmataMsg::AnalogMappingResponse(vec![None, Some(0)]),
+            FirmataMsg::PinStateResponse(PinState { pin: 3, mode: PinMode::PWM, state: 0 }),
+            FirmataMsg::PinStateResponse(PinState { pin: 4, mode: PinMode::Servo, state: 1500 }),
+            FirmataMsg::StringData("ok".to_string()),
+            FirmataMsg::I2cReply { address: 0x68, register: 0, data: vec![0x80, 0x01] },
+        ];
+
+        for msg in messages {
+            assert_eq!(parse(&msg.encode()[..]), Ok((EMPTY, msg.clone())));
+        }
+    }
 }
@@ -2,6 +2,8 @@
 
 /// Firmata analog pin value reporting
 pub const ANALOG_REPORT: u8 = 0xC0;
+/// Firmata digital port value reporting
+pub const DIGITAL_REPORT: u8 = 0xD0;
 
 /// The nybble representing an analog write or read report
 pub const ANALOG_MESSAGE: u8 = 0xE;
@@ -26,13 +28,45 @@ pub const CAPABILITY_RESPONSE: u8 = 0x6C;
 /// Firmata capability response record separator
 pub const CAPABILITY_RESPONSE_SEP: u8 = 0x7F;
 
+/// Firmata sysex analog pin mapping query
+pub const ANALOG_MAPPING_QUERY: u8 = 0x69;
+/// Firmata sysex analog pin mapping response
+pub const ANALOG_MAPPING_RESPONSE: u8 = 0x6A;
+/// The analog mapping entry of a pin without an analog channel
+pub const ANALOG_MAPPING_NONE: u8 = 0x7F;
+
+/// Firmata sysex pin state query
+pub const PIN_STATE_QUERY: u8 = 0x6D;
+/// Firmata sysex pin state response
+pub const PIN_STATE_RESPONSE: u8 = 0x6E;
+
+/// Firmata sysex analog write for pins beyond the 16 reachable by an analog message
+pub const EXTENDED_ANALOG: u8 = 0x6F;
+
+/// Firmata sysex request to change how often analog values are reported
+pub const SAMPLING_INTERVAL: u8 = 0x7A;
+
+/// Firmata sysex string message, sent by the device for debug output
+pub const STRING_DATA: u8 = 0x71;
+
+/// Firmata sysex I2C read or write request
+pub const I2C_REQUEST: u8 = 0x76;
+/// Firmata sysex response to an I2C read request
+pub const I2C_REPLY: u8 = 0x77;
+/// Firmata sysex I2C bus configuration
+pub const I2C_CONFIG: u8 = 0x78;
+/// The I2C request mode bits of a write
+pub const I2C_MODE_WRITE: u8 = 0x00;
+/// The I2C request mode bits of a single read
+pub const I2C_MODE_READ_ONCE: u8 = 0x08;
+
 /// The Firmata protocol version
 pub const PROTOCOL_VERSION: u8 = 0xF9;
 
 /// Firmata device reset request
 pub const RESET: u8 = 0xFF;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PinMode {
     DigitalInput,
     DigitalOutput,
@@ -41,6 +75,8 @@ pub enum PinMode {
     Servo,
     Shift,
     I2C,
+    /// A digital input with the internal pull-up resistor enabled.
+    PullUp,
     Other(u8),
 }
 
@@ -54,11 +90,68 @@ impl From<u8> for PinMode {
             0x04 => PinMode::Servo,
             0x05 => PinMode::Shift,
             0x06 => PinMode::I2C,
+            0x0B => PinMode::PullUp,
             n => PinMode::Other(n),
         }
     }
 }
 
+impl PinMode {
+    /// The modes with names, in the order of their mode numbers.
+    pub const NAMED: &'static [PinMode] = &[
+        PinMode::DigitalInput,
+        PinMode::DigitalOutput,
+        PinMode::AnalogInput,
+        PinMode::PWM,
+        PinMode::Servo,
+        PinMode::Shift,
+        PinMode::I2C,
+        PinMode::PullUp,
+    ];
+
+    /// Whether the pin reads a digital level reported in its port.
+    pub fn is_digital_input(self) -> bool {
+        self == PinMode::DigitalInput || self == PinMode::PullUp
+    }
+
+    /// A short name for the mode, as accepted by `str::parse`. Modes without
+    /// a name are shown by number.
+    pub fn name(self) -> String {
+        match self {
+            PinMode::DigitalInput => "input".into(),
+            PinMode::DigitalOutput => "output".into(),
+            PinMode::AnalogInput => "analog".into(),
+            PinMode::PWM => "pwm".into(),
+            PinMode::Servo => "servo".into(),
+            PinMode::Shift => "shift".into(),
+            PinMode::I2C => "i2c".into(),
+            PinMode::PullUp => "pullup".into(),
+            PinMode::Other(n) => format!("0x{:02x}", n),
+        }
+    }
+}
+
+impl ::std::str::FromStr for PinMode {
+    type Err = String;
+
+    /// Parse a mode name, or a mode number for modes without one.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.to_lowercase();
+        if let Some(&mode) = PinMode::NAMED.iter().find(|mode| mode.name() == s) {
+            return Ok(mode);
+        }
+        let number = match s.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        number
+            .ok()
+            .filter(|&n| n < 0x80)
+            .map(PinMode::from)
+            .ok_or_else(|| format!("Unknown pin mode {:?}", s))
+    }
+}
+
 impl From<PinMode> for u8 {
     fn from(item: PinMode) -> Self {
         match item {
@@ -69,18 +162,30 @@ impl From<PinMode> for u8 {
             PinMode::Servo => 0x04,
             PinMode::Shift => 0x05,
             PinMode::I2C => 0x06,
+            PinMode::PullUp => 0x0B,
             PinMode::Other(n) => n,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PinCapability {
     pub mode: PinMode,
     pub res: u8,
 }
 
-#[derive(Debug, PartialEq)]
+/// The mode and state of a pin, as reported in a pin state response.
+///
+/// For output pins the state is the last value written to the pin; for
+/// inputs it is usually whether the pull-up resistor is enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinState {
+    pub pin: u8,
+    pub mode: PinMode,
+    pub state: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FirmataMsg {
     QueryFirmware {
         major: u8,
@@ -93,8 +198,194 @@ pub enum FirmataMsg {
         pin: u8,
         value: u16,
     },
+    /// The levels of the 8 pins in a digital port, least significant pin first.
+    DigitalRead {
+        port: u8,
+        value: u8,
+    },
     ProtocolVersion {
         major: u8,
         minor: u8,
     },
+    /// The analog channel of each pin, indexed by pin number.
+    AnalogMappingResponse(Vec<Option<u8>>),
+    PinStateResponse(PinState),
+    StringData(String),
+    I2cReply {
+        address: u16,
+        register: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// A command sent by the host to a Firmata device.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCommand {
+    Reset,
+    QueryVersion,
+    QueryFirmware,
+    CapabilityQuery,
+    AnalogMappingQuery,
+    PinStateQuery(u8),
+    SetPinMode {
+        pin: u8,
+        mode: PinMode,
+    },
+    DigitalPortWrite {
+        port: u8,
+        value: u8,
+    },
+    DigitalWrite {
+        pin: u8,
+        level: bool,
+    },
+    /// A PWM or servo value, sent as an analog message or, for pins above
+    /// 15 and values above 14 bits, an extended analog sysex message.
+    AnalogWrite {
+        pin: u8,
+        value: u32,
+    },
+    AnalogReport {
+        channel: u8,
+        enabled: bool,
+    },
+    DigitalReport {
+        port: u8,
+        enabled: bool,
+    },
+    /// The interval between analog reports, in milliseconds.
+    SamplingInterval(u16),
+    /// A sysex command without a dedicated variant, starting with the
+    /// command byte.
+    Sysex(Vec<u8>),
+}
+
+impl HostCommand {
+    /// Encode the command the way a host sends it.
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            HostCommand::Reset => vec![RESET],
+            HostCommand::QueryVersion => vec![PROTOCOL_VERSION],
+            HostCommand::QueryFirmware => vec![START_SYSEX, QUERY_FIRMWARE, END_SYSEX],
+            HostCommand::CapabilityQuery => vec![START_SYSEX, CAPABILITY_QUERY, END_SYSEX],
+            HostCommand::AnalogMappingQuery => vec![START_SYSEX, ANALOG_MAPPING_QUERY, END_SYSEX],
+            HostCommand::PinStateQuery(pin) => vec![START_SYSEX, PIN_STATE_QUERY, pin, END_SYSEX],
+            HostCommand::SetPinMode { pin, mode } => vec![SET_PIN_MODE, pin, mode.into()],
+            HostCommand::DigitalPortWrite { port, value } => {
+                vec![DIGITAL_MESSAGE << 4 | port, value & 0x7F, value >> 7]
+            }
+            HostCommand::DigitalWrite { pin, level } => vec![DIGITAL_WRITE, pin, level as u8],
+            HostCommand::AnalogWrite { pin, value } if pin < 16 && value >> 14 == 0 => {
+                vec![ANALOG_MESSAGE << 4 | pin, (value & 0x7F) as u8, (value >> 7) as u8]
+            }
+            HostCommand::AnalogWrite { pin, value } => {
+                let mut buf = vec![START_SYSEX, EXTENDED_ANALOG, pin];
+                let mut value = value;
+                loop {
+                    buf.push((value & 0x7F) as u8);
+                    value >>= 7;
+                    if value == 0 {
+                        break;
+                    }
+                }
+                buf.push(END_SYSEX);
+                buf
+            }
+            HostCommand::AnalogReport { channel, enabled } => vec![ANALOG_REPORT | channel, enabled as u8],
+            HostCommand::DigitalReport { port, enabled } => vec![DIGITAL_REPORT | port, enabled as u8],
+            HostCommand::SamplingInterval(ms) => {
+                vec![START_SYSEX, SAMPLING_INTERVAL, (ms & 0x7F) as u8, (ms >> 7) as u8, END_SYSEX]
+            }
+            HostCommand::Sysex(ref data) => {
+                let mut buf = vec![START_SYSEX];
+                buf.extend_from_slice(data);
+                buf.push(END_SYSEX);
+                buf
+            }
+        }
+    }
+}
+
+/// Append `data` to `buf` as pairs of 7 bit bytes, least significant first.
+fn encode_pairs(buf: &mut Vec<u8>, data: &[u8]) {
+    for &byte in data {
+        buf.push(byte & 0x7F);
+        buf.push(byte >> 7);
+    }
+}
+
+impl FirmataMsg {
+    /// Encode the message the way a Firmata device sends it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match *self {
+            FirmataMsg::QueryFirmware { major, minor, ref firmware_name } => {
+                buf.extend_from_slice(&[START_SYSEX, QUERY_FIRMWARE, major, minor]);
+                buf.extend_from_slice(firmware_name);
+                buf.push(END_SYSEX);
+            }
+            FirmataMsg::CapabilityQuery => {
+                buf.extend_from_slice(&[START_SYSEX, CAPABILITY_QUERY, END_SYSEX]);
+            }
+            FirmataMsg::CapabilityResponse(ref pins) => {
+                buf.extend_from_slice(&[START_SYSEX, CAPABILITY_RESPONSE]);
+                for capabilities in pins {
+                    for capability in capabilities {
+                        buf.push(capability.mode.into());
+                        buf.push(capability.res);
+                    }
+                    buf.push(CAPABILITY_RESPONSE_SEP);
+                }
+                buf.push(END_SYSEX);
+            }
+            FirmataMsg::AnalogRead { pin, value } => {
+                buf.extend_from_slice(&[
+                    ANALOG_MESSAGE << 4 | pin,
+                    (value & 0x7F) as u8,
+                    (value >> 7) as u8,
+                ]);
+            }
+            FirmataMsg::DigitalRead { port, value } => {
+                buf.extend_from_slice(&[DIGITAL_MESSAGE << 4 | port, value & 0x7F, value >> 7]);
+            }
+            FirmataMsg::ProtocolVersion { major, minor } => {
+                buf.extend_from_slice(&[PROTOCOL_VERSION, major, minor]);
+            }
+            FirmataMsg::AnalogMappingResponse(ref channels) => {
+                buf.extend_from_slice(&[START_SYSEX, ANALOG_MAPPING_RESPONSE]);
+                buf.extend(channels.iter().map(|channel| channel.unwrap_or(ANALOG_MAPPING_NONE)));
+                buf.push(END_SYSEX);
+            }
+            FirmataMsg::PinStateResponse(ref state) => {
+                buf.extend_from_slice(&[START_SYSEX, PIN_STATE_RESPONSE, state.pin, state.mode.into()]);
+                let mut value = state.state;
+                loop {
+                    buf.push((value & 0x7F) as u8);
+                    value >>= 7;
+                    if value == 0 {
+                        break;
+                    }
+                }
+                buf.push(END_SYSEX);
+            }
+            FirmataMsg::StringData(ref string) => {
+                buf.extend_from_slice(&[START_SYSEX, STRING_DATA]);
+                encode_pairs(&mut buf, string.as_bytes());
+                buf.push(END_SYSEX);
+            }
+            FirmataMsg::I2cReply { address, register, ref data } => {
+                buf.extend_from_slice(&[
+                    START_SYSEX,
+                    I2C_REPLY,
+                    (address & 0x7F) as u8,
+                    (address >> 7) as u8,
+                    (register & 0x7F) as u8,
+                    (register >> 7) as u8,
+                ]);
+                encode_pairs(&mut buf, data);
+                buf.push(END_SYSEX);
+            }
+        }
+        buf
+    }
 }
@@ -0,0 +1,187 @@
+//! A virtual serial port backed by an emulated device.
+//!
+//! `VirtualBoard` creates a pseudo-terminal pair and runs an `Emulator` on
+//! the master side. The slave side is a real TTY, so `Connection::open` can
+//! be exercised end to end, including port configuration, read timeouts and
+//! partial reads, without hardware attached. `Pty` is the bare pair, for
+//! tools that present some other stream as a serial port.
+
+use ::emulator::Emulator;
+use libc;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// How long the device thread waits for the host before checking for
+/// output and shutdown.
+const POLL_TIMEOUT_MS: libc::c_int = 5;
+
+/// A pseudo-terminal pair with the master side in raw mode.
+pub struct Pty {
+    master: File,
+    path: PathBuf,
+    // Holding the slave open keeps reads from the master from failing with
+    // EIO while no program has the port open.
+    _slave: File,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        let master = open_master()?;
+        let path = slave_path(&master)?;
+        let slave = File::options().read(true).write(true).open(&path)?;
+        make_raw(&master)?;
+        Ok(Pty { master, path, _slave: slave })
+    }
+
+    /// The path of the slave TTY, for the program on the other end.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The master side, carrying the bytes written to and read from the slave.
+    pub fn master(&self) -> &File {
+        &self.master
+    }
+}
+
+/// An emulated device attached to the slave side of a pseudo-terminal.
+///
+/// The device runs on its own thread until the `VirtualBoard` is dropped.
+pub struct VirtualBoard {
+    pty: Pty,
+    device: Emulator,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl VirtualBoard {
+    /// Attach `device` to a new pseudo-terminal.
+    pub fn spawn(device: Emulator) -> io::Result<Self> {
+        let pty = Pty::open()?;
+        let master = pty.master().try_clone()?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (device, shutdown) = (device.clone(), shutdown.clone());
+            thread::spawn(move || run(master, device, &shutdown))
+        };
+        debug!("Emulated Firmata device listening on {}", pty.path().display());
+        Ok(VirtualBoard { pty, device, shutdown, thread: Some(thread) })
+    }
+
+    /// The path of the slave TTY, to pass to `Connection::open`.
+    pub fn path(&self) -> &Path {
+        self.pty.path()
+    }
+
+    /// The emulated device, for changing inputs and inspecting its state.
+    pub fn device(&self) -> &Emulator {
+        &self.device
+    }
+}
+
+impl Drop for VirtualBoard {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Shuttle bytes between the master side of the pty and the device.
+fn run(mut master: File, mut device: Emulator, shutdown: &AtomicBool) {
+    let mut buf = [0; 256];
+    while !shutdown.load(Ordering::SeqCst) {
+        let mut pollfd = libc::pollfd { fd: master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) };
+        if ready > 0 && pollfd.revents & libc::POLLIN != 0 {
+            match master.read(&mut buf) {
+                Ok(len) => {
+                    let _ = device.write_all(&buf[..len]);
+                }
+                Err(e) => {
+                    warn!("Virtual board stopped reading: {}", e);
+                    return;
+                }
+            }
+        }
+
+        while let Ok(len) = device.read(&mut buf) {
+            if let Err(e) = master.write_all(&buf[..len]) {
+                warn!("Virtual board stopped writing: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn open_master() -> io::Result<File> {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(master)
+    }
+}
+
+fn slave_path(master: &File) -> io::Result<PathBuf> {
+    let mut name = [0 as libc::c_char; 128];
+    let err = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Ok(PathBuf::from(name.to_string_lossy().into_owned()))
+}
+
+/// Turn off echo and line editing, so the device sees the host's bytes
+/// exactly as written even before a connection configures the port.
+fn make_raw(master: &File) -> io::Result<()> {
+    unsafe {
+        let mut termios = ::std::mem::zeroed();
+        if libc::tcgetattr(master.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(master.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connection::Connection;
+    use protocol::PinMode;
+    use std::time::Duration;
+
+    #[test]
+    fn connection_opens_virtual_board() {
+        let board = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let mut conn = Connection::open(board.path().to_str().unwrap()).unwrap();
+        conn.resync().unwrap();
+        let report = conn.initialize_with(Duration::from_millis(500)).unwrap();
+        assert!(report.is_complete(), "{:?}", report);
+        assert_eq!(conn.board().unwrap().pin_count(), 20);
+
+        conn.set_pin_mode(9, PinMode::PWM).unwrap();
+        conn.analog_write(9, 128).unwrap();
+        let state = conn.query_pin_state(9, Duration::from_millis(500)).unwrap();
+        assert_eq!((state.mode, state.state), (PinMode::PWM, 128));
+        assert_eq!(board.device().pin_value(9), Some(128));
+    }
+}
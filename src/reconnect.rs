@@ -0,0 +1,284 @@
+//! Reestablishing serial connections to Firmata devices that went away.
+//!
+//! When a USB cable is unplugged the serial device disappears and every read
+//! fails. A `Reconnector` waits for the device to come back, reopens it,
+//! resynchronizes, and replays the host's pin configuration so that the
+//! application can carry on with the same `Board`.
+
+use ::board::Board;
+use ::connection::{Connection, ConnectionBuilder, RW};
+use ::discovery::SerialPortInfo;
+use ::errors::*;
+use serial_core;
+use serial_unix;
+use std::{io, thread, time};
+
+/// How often and how long to keep trying to reopen a device.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Give up after this many attempts, or never if `None`.
+    pub max_attempts: Option<usize>,
+    /// The delay before the first attempt, doubled after each failure.
+    pub initial_backoff: time::Duration,
+    /// The upper bound of the delay between attempts.
+    pub max_backoff: time::Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: time::Duration::from_millis(250),
+            max_backoff: time::Duration::from_secs(5),
+        }
+    }
+}
+
+/// A way to reestablish a connection whose device went away.
+pub trait Reconnect<T>: Send
+where
+    T: RW
+{
+    /// Open a new connection carrying over the state recorded in `board`.
+    fn reopen(&self, board: Board) -> Result<Connection<T>>;
+}
+
+/// Reopens a serial connection by path, or by its `/dev/serial/by-id` link.
+///
+/// The by-id link is tried first because the kernel may assign a different
+/// `ttyACM` number when the device is plugged back in.
+#[derive(Debug, Clone)]
+pub struct Reconnector {
+    path: String,
+    by_id: Option<String>,
+    builder: ConnectionBuilder,
+    policy: ReconnectPolicy,
+}
+
+impl Reconnector {
+    pub fn new(path: &str) -> Self {
+        Reconnector {
+            path: path.to_string(),
+            by_id: None,
+            builder: ConnectionBuilder::new(),
+            policy: ReconnectPolicy::default(),
+        }
+    }
+
+    /// Reconnect to a port found by `discovery::ports`.
+    pub fn from_port(port: &SerialPortInfo) -> Self {
+        let mut reconnector = Reconnector::new(&port.path.to_string_lossy());
+        reconnector.by_id = port.by_id.as_ref().map(|link| link.to_string_lossy().into_owned());
+        reconnector
+    }
+
+    pub fn by_id(mut self, link: &str) -> Self {
+        self.by_id = Some(link.to_string());
+        self
+    }
+
+    /// Set the serial settings used to reopen the device.
+    pub fn builder(mut self, builder: ConnectionBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    pub fn policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Close a broken connection and replace it with a reopened one.
+    pub fn reconnect(&self, conn: &mut Connection<serial_unix::TTYPort>) -> Result<()> {
+        let board = conn.close().unwrap_or_default();
+        *conn = self.reopen(board)?;
+        Ok(())
+    }
+
+    /// Reopen the device with backoff, then resync and replay the pin modes
+    /// and reporting subscriptions recorded in `board`.
+    ///
+    /// A failed replay is retried like a failed open, since a device that
+    /// was just plugged back in may still drop the first writes.
+    pub fn reopen(&self, mut board: Board) -> Result<Connection<serial_unix::TTYPort>> {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            thread::sleep(backoff);
+
+            match self.try_open() {
+                Ok(inner) => {
+                    let mut conn = Connection::Open { inner, board };
+                    match conn.replay() {
+                        Ok(()) => {
+                            info!("Reconnected to Firmata device on {} after {} attempt(s)", self.path, attempt);
+                            return Ok(conn);
+                        }
+                        Err(e) => {
+                            debug!("Replaying the configuration to {} failed: {}", self.path, e);
+                            board = conn.close().unwrap_or_default();
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!("Reconnect attempt {} to {} failed: {}", attempt, self.path, e);
+                }
+            }
+
+            if self.policy.max_attempts.is_some_and(|max| attempt >= max) {
+                warn!("Giving up reconnecting to {} after {} attempts", self.path, attempt);
+                return Err(ErrorKind::ConnectionClosed.into());
+            }
+            backoff = ::std::cmp::min(backoff * 2, self.policy.max_backoff);
+        }
+    }
+
+    /// Open and resync the device, returning the underlying port.
+    ///
+    /// Each candidate path is tried in turn and the last error is returned
+    /// only once all of them have failed.
+    fn try_open(&self) -> Result<serial_unix::TTYPort> {
+        let mut last_err = None;
+        for path in self.by_id.iter().chain(Some(&self.path)) {
+            let mut conn = match self.builder.open(path) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    last_err = Some(e);
+                    continue;
+                }
+            };
+            if let Err(e) = conn.resync() {
+                debug!("Unable to resync with {}: {}", path, e);
+                last_err = Some(e);
+                continue;
+            }
+            if let Connection::Open { inner, .. } = conn {
+                return Ok(inner);
+            }
+        }
+        Err(last_err.unwrap_or_else(|| ErrorKind::ConnectionClosed.into()))
+    }
+}
+
+impl Reconnect<serial_unix::TTYPort> for Reconnector {
+    fn reopen(&self, board: Board) -> Result<Connection<serial_unix::TTYPort>> {
+        Reconnector::reopen(self, board)
+    }
+}
+
+/// Whether an error means the underlying device is gone, as opposed to a
+/// timeout or a garbled message that the next read may recover from.
+pub fn is_disconnect(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::ConnectionClosed => true,
+        ErrorKind::Io(ref e) => is_fatal_io(e.kind()),
+        ErrorKind::Serial(ref e) => match e.kind() {
+            serial_core::ErrorKind::NoDevice => true,
+            serial_core::ErrorKind::Io(kind) => is_fatal_io(kind),
+            serial_core::ErrorKind::InvalidInput => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_fatal_io(kind: io::ErrorKind) -> bool {
+    !matches!(
+        kind,
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::PinMode;
+
+    fn quick_policy(max_attempts: usize) -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: Some(max_attempts),
+            initial_backoff: time::Duration::from_millis(1),
+            max_backoff: time::Duration::from_millis(1),
+        }
+    }
+
+    fn io_error(kind: io::ErrorKind) -> Error {
+        io::Error::new(kind, "test").into()
+    }
+
+    #[test]
+    fn timeouts_are_not_disconnects() {
+        assert!(!is_disconnect(&io_error(io::ErrorKind::TimedOut)));
+        assert!(!is_disconnect(&ErrorKind::UnreadableMsg.into()));
+    }
+
+    #[test]
+    fn lost_devices_are_disconnects() {
+        assert!(is_disconnect(&io_error(io::ErrorKind::UnexpectedEof)));
+        assert!(is_disconnect(&io_error(io::ErrorKind::BrokenPipe)));
+        assert!(is_disconnect(&ErrorKind::ConnectionClosed.into()));
+        assert!(is_disconnect(&serial_core::Error::new(
+            serial_core::ErrorKind::NoDevice,
+            "test"
+        ).into()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reopen_replays_host_configuration() {
+        use emulator::Emulator;
+        use pty::VirtualBoard;
+
+        let device = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let mut board = Board::default();
+        board.record_mode(13, PinMode::DigitalOutput);
+        board.record_analog_report(0, true);
+        board.record_digital_report(1, true);
+
+        // The missing by-id link is skipped in favour of the path.
+        let conn = Reconnector::new(device.path().to_str().unwrap())
+            .by_id("/dev/serial/by-id/missing")
+            .policy(quick_policy(1))
+            .reopen(board)
+            .unwrap();
+        assert_eq!(conn.board().unwrap().modes[&13], PinMode::DigitalOutput);
+
+        let emulator = device.device();
+        let deadline = time::Instant::now() + time::Duration::from_secs(1);
+        while emulator.pin_mode(13) != Some(PinMode::DigitalOutput)
+            || !emulator.is_analog_reporting(0)
+            || !emulator.is_digital_reporting(1)
+        {
+            assert!(time::Instant::now() < deadline, "configuration was not replayed");
+            thread::sleep(time::Duration::from_millis(5));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reopen_tries_the_path_when_the_link_does_not_answer() {
+        use emulator::Emulator;
+        use pty::{Pty, VirtualBoard};
+
+        let device = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        // Nothing answers on the other side of this pty, so resync fails.
+        let silent = Pty::open().unwrap();
+
+        let mut conn = Reconnector::new(device.path().to_str().unwrap())
+            .by_id(silent.path().to_str().unwrap())
+            .policy(quick_policy(1))
+            .reopen(Board::default())
+            .unwrap();
+        let firmware = conn.query_firmware_info(::connection::DEFAULT_QUERY_TIMEOUT).unwrap();
+        assert_eq!(firmware.name, "StandardFirmata.ino");
+    }
+
+    #[test]
+    fn reopen_gives_up_after_max_attempts() {
+        let reconnector = Reconnector::new("/dev/firmata-missing").policy(quick_policy(2));
+        match reconnector.reopen(Board::default()) {
+            Err(Error(ErrorKind::ConnectionClosed, _)) => {}
+            other => panic!("Expected to give up, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! The device side of the Firmata protocol.
+//!
+//! A `Server` plays the role of the firmware: it decodes the commands a
+//! Firmata host sends, keeps track of pin modes and reporting, and drives a
+//! `Hardware` implementation that does the actual I/O. This lets a Linux
+//! machine's GPIO or a simulated plant be controlled by any Firmata host.
+
+use ::board::{Firmware, Protocol};
+use ::connection::RW;
+use ::parser;
+use ::protocol::*;
+use nom;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time;
+
+/// How often StandardFirmata reports analog values unless the host asks
+/// for another sampling interval.
+pub const DEFAULT_SAMPLING_INTERVAL: time::Duration = time::Duration::from_millis(19);
+
+/// The hardware a `Server` exposes to Firmata hosts.
+///
+/// The server validates pin modes against `capabilities` and tracks what
+/// the host asked for, so implementations only have to perform the I/O.
+/// Errors are reported to the host as string messages.
+pub trait Hardware {
+    fn firmware(&self) -> Firmware;
+
+    fn protocol(&self) -> Protocol {
+        Protocol(2, 5)
+    }
+
+    /// The modes each pin supports, indexed by pin number.
+    fn capabilities(&self) -> Vec<Vec<PinCapability>>;
+
+    /// The analog channel of each pin, indexed by pin number.
+    fn analog_mapping(&self) -> Vec<Option<u8>>;
+
+    fn set_pin_mode(&mut self, pin: u8, mode: PinMode) -> io::Result<()>;
+
+    fn digital_write(&mut self, pin: u8, level: bool) -> io::Result<()>;
+
+    /// Write a PWM duty cycle or servo position.
+    fn analog_write(&mut self, pin: u8, value: u32) -> io::Result<()>;
+
+    fn digital_read(&mut self, pin: u8) -> io::Result<bool>;
+
+    fn analog_read(&mut self, pin: u8) -> io::Result<u16>;
+
+    /// Return the hardware to its power-on state.
+    fn reset(&mut self) {}
+
+    /// Handle a sysex command the server doesn't know, returning the
+    /// messages to send back. `data` starts with the sysex command byte.
+    fn sysex(&mut self, data: &[u8]) -> Vec<FirmataMsg> {
+        trace!("Ignoring sysex command {:?}", data.first());
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ServerPin {
+    mode: PinMode,
+    /// The value written by the host, or whether the pull-up is enabled.
+    state: u32,
+}
+
+pub struct Server<H>
+where
+    H: Hardware
+{
+    hardware: H,
+    capabilities: Vec<Vec<PinCapability>>,
+    analog_mapping: Vec<Option<u8>>,
+    pins: Vec<ServerPin>,
+    analog_reports: HashSet<u8>,
+    digital_reports: HashSet<u8>,
+    /// The last value reported for each digital port.
+    reported_ports: HashMap<u8, u8>,
+    sampling_interval: time::Duration,
+    /// Bytes from the host that don't form a complete command yet.
+    input: Vec<u8>,
+}
+
+impl<H> Server<H>
+where
+    H: Hardware
+{
+    pub fn new(hardware: H) -> Self {
+        let mut server = Server {
+            capabilities: hardware.capabilities(),
+            analog_mapping: hardware.analog_mapping(),
+            hardware,
+            pins: Vec::new(),
+            analog_reports: HashSet::new(),
+            digital_reports: HashSet::new(),
+            reported_ports: HashMap::new(),
+            sampling_interval: DEFAULT_SAMPLING_INTERVAL,
+            input: Vec::new(),
+        };
+        server.reset();
+        server
+    }
+
+    pub fn hardware(&self) -> &H {
+        &self.hardware
+    }
+
+    pub fn hardware_mut(&mut self) -> &mut H {
+        &mut self.hardware
+    }
+
+    /// The mode the host set a pin to.
+    pub fn pin_mode(&self, pin: u8) -> Option<PinMode> {
+        self.pins.get(pin as usize).map(|p| p.mode)
+    }
+
+    /// The value the host wrote to a pin.
+    pub fn pin_value(&self, pin: u8) -> Option<u32> {
+        self.pins.get(pin as usize).map(|p| p.state)
+    }
+
+    pub fn is_analog_reporting(&self, channel: u8) -> bool {
+        self.analog_reports.contains(&channel)
+    }
+
+    pub fn is_digital_reporting(&self, port: u8) -> bool {
+        self.digital_reports.contains(&port)
+    }
+
+    /// How often the host asked for analog values to be reported.
+    pub fn sampling_interval(&self) -> time::Duration {
+        self.sampling_interval
+    }
+
+    /// Restore the state StandardFirmata starts in: analog pins read analog
+    /// values, every other pin is an output, and nothing is reported.
+    pub fn reset(&mut self) {
+        let mapping = &self.analog_mapping;
+        self.pins = (0..self.capabilities.len())
+            .map(|pin| {
+                let analog = mapping.get(pin).is_some_and(|c| c.is_some());
+                let mode = if analog { PinMode::AnalogInput } else { PinMode::DigitalOutput };
+                ServerPin { mode, state: 0 }
+            })
+            .collect();
+        self.analog_reports.clear();
+        self.digital_reports.clear();
+        self.reported_ports.clear();
+        self.sampling_interval = DEFAULT_SAMPLING_INTERVAL;
+        self.hardware.reset();
+    }
+
+    /// Take bytes from the host, act on every complete command in them, and
+    /// return the messages to send back.
+    pub fn receive(&mut self, bytes: &[u8]) -> Vec<FirmataMsg> {
+        let mut responses = Vec::new();
+        self.input.extend_from_slice(bytes);
+        while !self.input.is_empty() {
+            let parsed = match parser::parse_command(&self.input) {
+                Ok((rest, command)) => Some((self.input.len() - rest.len(), command)),
+                Err(nom::Err::Incomplete(_)) => break,
+                Err(_) => None,
+            };
+            match parsed {
+                Some((len, command)) => {
+                    self.input.drain(..len);
+                    self.handle(command, &mut responses);
+                }
+                None => {
+                    trace!("Ignoring byte {:#04X}", self.input[0]);
+                    self.input.remove(0);
+                }
+            }
+        }
+        responses
+    }
+
+    /// Report the digital ports with reporting enabled whose levels changed
+    /// since they were last reported.
+    pub fn report_digital(&mut self) -> Vec<FirmataMsg> {
+        let mut ports: Vec<u8> = self.digital_reports.iter().cloned().collect();
+        ports.sort();
+        ports.into_iter()
+            .filter_map(|port| {
+                let value = self.port_value(port);
+                if self.reported_ports.insert(port, value) == Some(value) {
+                    None
+                } else {
+                    Some(FirmataMsg::DigitalRead { port, value })
+                }
+            })
+            .collect()
+    }
+
+    /// Report the value of every analog channel with reporting enabled, as
+    /// the firmware does once per sampling interval.
+    pub fn report_analog(&mut self) -> Vec<FirmataMsg> {
+        let mut channels: Vec<(u8, u8)> = self.analog_mapping
+            .iter()
+            .enumerate()
+            .filter_map(|(pin, channel)| channel.map(|channel| (channel, pin as u8)))
+            .filter(|&(channel, _)| self.analog_reports.contains(&channel))
+            .collect();
+        channels.sort();
+        channels.into_iter()
+            .filter_map(|(channel, pin)| match self.hardware.analog_read(pin) {
+                Ok(value) => Some(FirmataMsg::AnalogRead { pin: channel, value }),
+                Err(e) => {
+                    warn!("Unable to read analog pin {}: {}", pin, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Serve a host over `conn` until it closes the stream.
+    ///
+    /// The stream should have a read timeout, as serial ports and
+    /// `tcp::TcpPort` do, so that reports keep flowing while the host is
+    /// quiet.
+    pub fn serve<T>(&mut self, conn: &mut T) -> io::Result<()>
+    where
+        T: RW
+    {
+        let mut buf = [0; 256];
+        let mut last_sample = time::Instant::now();
+        loop {
+            let mut messages = match conn.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(len) => self.receive(&buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+                    Vec::new()
+                }
+                Err(e) => return Err(e),
+            };
+            messages.extend(self.report_digital());
+            if last_sample.elapsed() >= self.sampling_interval {
+                last_sample = time::Instant::now();
+                messages.extend(self.report_analog());
+            }
+            for msg in messages {
+                conn.write_all(&msg.encode())?;
+            }
+        }
+    }
+
+    fn handle(&mut self, command: HostCommand, responses: &mut Vec<FirmataMsg>) {
+        match command {
+            HostCommand::Reset => self.reset(),
+            HostCommand::QueryVersion => {
+                let Protocol(major, minor) = self.hardware.protocol();
+                responses.push(FirmataMsg::ProtocolVersion { major, minor });
+            }
+            HostCommand::QueryFirmware => {
+                let firmware = self.hardware.firmware();
+                responses.push(FirmataMsg::QueryFirmware {
+                    major: firmware.major,
+                    minor: firmware.minor,
+                    firmware_name: firmware.name.into_bytes(),
+                });
+            }
+            HostCommand::CapabilityQuery => {
+                responses.push(FirmataMsg::CapabilityResponse(self.capabilities.clone()));
+            }
+            HostCommand::AnalogMappingQuery => {
+                responses.push(FirmataMsg::AnalogMappingResponse(self.analog_mapping.clone()));
+            }
+            HostCommand::PinStateQuery(pin) => {
+                if let Some(p) = self.pins.get(pin as usize) {
+                    let state = PinState { pin, mode: p.mode, state: p.state };
+                    responses.push(FirmataMsg::PinStateResponse(state));
+                }
+            }
+            HostCommand::SetPinMode { pin, mode } => self.set_pin_mode(pin, mode, responses),
+            HostCommand::DigitalWrite { pin, level } => self.digital_write(pin, level, responses),
+            HostCommand::DigitalPortWrite { port, value } => {
+                for bit in 0..8 {
+                    let pin = port * 8 + bit;
+                    if self.pin_mode(pin) == Some(PinMode::DigitalOutput) {
+                        self.digital_write(pin, value >> bit & 1 == 1, responses);
+                    }
+                }
+            }
+            HostCommand::AnalogWrite { pin, value } => self.analog_write(pin, value, responses),
+            HostCommand::AnalogReport { channel, enabled } => {
+                if enabled {
+                    self.analog_reports.insert(channel);
+                } else {
+                    self.analog_reports.remove(&channel);
+                }
+            }
+            HostCommand::DigitalReport { port, enabled } => {
+                if enabled {
+                    // Enabling reporting sends the current levels right away.
+                    self.digital_reports.insert(port);
+                    let value = self.port_value(port);
+                    self.reported_ports.insert(port, value);
+                    responses.push(FirmataMsg::DigitalRead { port, value });
+                } else {
+                    self.digital_reports.remove(&port);
+                }
+            }
+            HostCommand::SamplingInterval(ms) => {
+                self.sampling_interval = time::Duration::from_millis(u64::from(ms));
+            }
+            HostCommand::Sysex(data) => responses.extend(self.hardware.sysex(&data)),
+        }
+    }
+
+    fn set_pin_mode(&mut self, pin: u8, mode: PinMode, responses: &mut Vec<FirmataMsg>) {
+        let supported = self.capabilities
+            .get(pin as usize)
+            .is_some_and(|modes| modes.iter().any(|cap| cap.mode == mode));
+        if !supported {
+            responses.push(FirmataMsg::StringData("Unknown pin mode".into()));
+            return;
+        }
+        match self.hardware.set_pin_mode(pin, mode) {
+            Ok(()) => {
+                let p = &mut self.pins[pin as usize];
+                p.mode = mode;
+                p.state = if mode == PinMode::PullUp { 1 } else { 0 };
+            }
+            Err(e) => report_error(responses, "set mode of", pin, &e),
+        }
+    }
+
+    fn digital_write(&mut self, pin: u8, level: bool, responses: &mut Vec<FirmataMsg>) {
+        if self.pin_mode(pin) != Some(PinMode::DigitalOutput) {
+            return;
+        }
+        match self.hardware.digital_write(pin, level) {
+            Ok(()) => self.pins[pin as usize].state = u32::from(level),
+            Err(e) => report_error(responses, "write", pin, &e),
+        }
+    }
+
+    fn analog_write(&mut self, pin: u8, value: u32, responses: &mut Vec<FirmataMsg>) {
+        match self.pin_mode(pin) {
+            Some(PinMode::PWM) | Some(PinMode::Servo) => {}
+            _ => return,
+        }
+        match self.hardware.analog_write(pin, value) {
+            Ok(()) => self.pins[pin as usize].state = value,
+            Err(e) => report_error(responses, "write", pin, &e),
+        }
+    }
+
+    fn port_value(&mut self, port: u8) -> u8 {
+        let mut value = 0;
+        for bit in 0..8 {
+            let pin = port * 8 + bit;
+            if !self.pin_mode(pin).is_some_and(PinMode::is_digital_input) {
+                continue;
+            }
+            match self.hardware.digital_read(pin) {
+                Ok(true) => value |= 1 << bit,
+                Ok(false) => {}
+                Err(e) => warn!("Unable to read digital pin {}: {}", pin, e),
+            }
+        }
+        value
+    }
+}
+
+fn report_error(responses: &mut Vec<FirmataMsg>, action: &str, pin: u8, err: &io::Error) {
+    warn!("Unable to {} pin {}: {}", action, pin, err);
+    responses.push(FirmataMsg::StringData(format!("Unable to {} pin {}: {}", action, pin, err)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use tcp::TcpPort;
+
+    /// Four digital pins, the last of which is also analog channel 0.
+    #[derive(Default)]
+    struct Plant {
+        levels: [bool; 4],
+        written: Vec<(u8, bool)>,
+    }
+
+    impl Hardware for Plant {
+        fn firmware(&self) -> Firmware {
+            Firmware { major: 1, minor: 0, name: "Plant".into() }
+        }
+
+        fn capabilities(&self) -> Vec<Vec<PinCapability>> {
+            let digital = vec![
+                PinCapability { mode: PinMode::DigitalInput, res: 1 },
+                PinCapability { mode: PinMode::DigitalOutput, res: 1 },
+            ];
+            let mut analog = digital.clone();
+            analog.push(PinCapability { mode: PinMode::AnalogInput, res: 10 });
+            vec![digital.clone(), digital.clone(), digital, analog]
+        }
+
+        fn analog_mapping(&self) -> Vec<Option<u8>> {
+            vec![None, None, None, Some(0)]
+        }
+
+        fn set_pin_mode(&mut self, _pin: u8, _mode: PinMode) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn digital_write(&mut self, pin: u8, level: bool) -> io::Result<()> {
+            if pin == 2 {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "pin is locked"));
+            }
+            self.written.push((pin, level));
+            Ok(())
+        }
+
+        fn analog_write(&mut self, _pin: u8, _value: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn digital_read(&mut self, pin: u8) -> io::Result<bool> {
+            Ok(self.levels[pin as usize])
+        }
+
+        fn analog_read(&mut self, _pin: u8) -> io::Result<u16> {
+            Ok(512)
+        }
+    }
+
+    #[test]
+    fn dispatches_commands_to_hardware() {
+        let mut server = Server::new(Plant::default());
+        let mut host = Vec::new();
+        ::client::set_pin_mode(&mut host, 0, PinMode::DigitalInput).unwrap();
+        ::client::digital_port_write(&mut host, 0, 0x06).unwrap();
+        ::client::digital_report(&mut host, 0, true).unwrap();
+        ::client::set_pin_mode(&mut host, 1, PinMode::PWM).unwrap();
+
+        // Split the commands to check partial commands wait for the rest.
+        let mut responses = server.receive(&host[..4]);
+        responses.extend(server.receive(&host[4..]));
+
+        assert_eq!(server.hardware().written, vec![(1, true)]);
+        assert_eq!(server.pin_value(1), Some(1));
+        assert_eq!(server.pin_mode(0), Some(PinMode::DigitalInput));
+        assert_eq!(responses, vec![
+            FirmataMsg::StringData("Unable to write pin 2: pin is locked".into()),
+            FirmataMsg::DigitalRead { port: 0, value: 0 },
+            FirmataMsg::StringData("Unknown pin mode".into()),
+        ]);
+
+        server.hardware_mut().levels[0] = true;
+        assert_eq!(server.report_digital(), vec![FirmataMsg::DigitalRead { port: 0, value: 1 }]);
+        assert_eq!(server.report_digital(), vec![]);
+    }
+
+    #[test]
+    fn serves_a_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let device = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(time::Duration::from_millis(5))).unwrap();
+            let mut port = TcpPort::from(stream);
+            Server::new(Plant::default()).serve(&mut port)
+        });
+
+        let mut conn = ::connection::Connection::connect_tcp(addr).unwrap();
+        let report = conn.initialize().unwrap();
+        assert!(report.is_complete(), "{:?}", report);
+        assert_eq!(conn.board().unwrap().firmware.as_ref().unwrap().name, "Plant");
+
+        conn.analog_report(0, true).unwrap();
+        for _ in 0..50 {
+            if conn.board().unwrap().pins[&3].value == 512 {
+                break;
+            }
+            let _ = conn.update();
+        }
+        assert_eq!(conn.board().unwrap().pins[&3].value, 512);
+
+        conn.close();
+        device.join().unwrap().unwrap();
+    }
+}
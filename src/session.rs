@@ -0,0 +1,368 @@
+//! Recording and replaying the traffic of a Firmata link.
+//!
+//! A `Recorder` wraps a transport and writes every chunk read or written
+//! to a session, one line per chunk:
+//!
+//! ```text
+//! 0.012345 > F0 79 F7
+//! 0.031250 < F9 02 05
+//! ```
+//!
+//! The first field is the time since recording started in seconds, `>` marks
+//! bytes sent by the host and `<` bytes sent by the device. Lines starting
+//! with `#` are comments. A `Replay` feeds the device side of a session back
+//! to a `Connection`, so bugs seen in the field can be reproduced offline.
+
+use ::connection::RW;
+use ::parser::Direction;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A chunk of bytes sent across a link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// When the chunk was sent, relative to the start of the session.
+    pub at: Duration,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+impl Entry {
+    /// Write the entry as a line of a session file.
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let arrow = match self.direction {
+            Direction::HostToDevice => '>',
+            Direction::DeviceToHost => '<',
+        };
+        write!(out, "{}.{:06} {}", self.at.as_secs(), self.at.subsec_micros(), arrow)?;
+        for byte in &self.bytes {
+            write!(out, " {:02X}", byte)?;
+        }
+        writeln!(out)
+    }
+
+    /// Parse a line of a session file.
+    pub fn parse(line: &str) -> io::Result<Entry> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid session line {:?}", line));
+        let mut fields = line.split_whitespace();
+        let at = fields
+            .next()
+            .and_then(|at| at.parse::<f64>().ok())
+            .and_then(|at| Duration::try_from_secs_f64(at).ok())
+            .ok_or_else(invalid)?;
+        let direction = match fields.next() {
+            Some(">") => Direction::HostToDevice,
+            Some("<") => Direction::DeviceToHost,
+            _ => return Err(invalid()),
+        };
+        let bytes = fields
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        // The recorder only logs device reads that returned data, and an
+        // empty chunk would read as the end of the replayed stream.
+        if direction == Direction::DeviceToHost && bytes.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Entry { at, direction, bytes })
+    }
+}
+
+/// The traffic of a recorded link.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub entries: Vec<Entry>,
+}
+
+impl Session {
+    pub fn read<R: BufRead>(input: R) -> io::Result<Session> {
+        let mut entries = Vec::new();
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            entries.push(Entry::parse(line)?);
+        }
+        Ok(Session { entries })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Session> {
+        Session::read(BufReader::new(File::open(path)?))
+    }
+
+    /// The bytes sent in one direction, in order.
+    pub fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.direction == direction)
+            .flat_map(|entry| entry.bytes.iter().cloned())
+            .collect()
+    }
+}
+
+/// A transport that writes the traffic passing through it to a session.
+pub struct Recorder<T, W>
+where
+    T: RW,
+    W: io::Write + Send
+{
+    inner: T,
+    out: W,
+    start: Instant,
+}
+
+impl<T, W> Recorder<T, W>
+where
+    T: RW,
+    W: io::Write + Send
+{
+    pub fn new(inner: T, out: W) -> Self {
+        Recorder { inner, out, start: Instant::now() }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Stop recording, returning the transport and the session output.
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.out)
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let entry = Entry { at: self.start.elapsed(), direction, bytes: bytes.to_vec() };
+        // A failing session file shouldn't break the link being recorded.
+        if let Err(e) = entry.write_to(&mut self.out).and_then(|()| self.out.flush()) {
+            warn!("Unable to record Firmata session: {}", e);
+        }
+    }
+}
+
+impl<T, W> io::Read for Recorder<T, W>
+where
+    T: RW,
+    W: io::Write + Send
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if len > 0 {
+            self.record(Direction::DeviceToHost, &buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+impl<T, W> io::Write for Recorder<T, W>
+where
+    T: RW,
+    W: io::Write + Send
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if len > 0 {
+            self.record(Direction::HostToDevice, &buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How a `Replay` paces the recorded device output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// Deliver each chunk when it was originally received.
+    Original,
+    /// Deliver the session this many times faster than it was recorded.
+    /// The factor must be positive.
+    Accelerated(f64),
+    /// Deliver every chunk as soon as it is read.
+    Immediate,
+}
+
+/// A transport that plays back what the device sent in a session.
+///
+/// Reads return the recorded device output. While the next chunk isn't due
+/// yet, reads block for at most the read timeout and then fail with
+/// `io::ErrorKind::TimedOut`, like a serial port. Once the session is
+/// exhausted reads return end of stream. Writes are kept for inspection.
+pub struct Replay {
+    chunks: Vec<(Duration, Vec<u8>)>,
+    next: usize,
+    offset: usize,
+    timing: Timing,
+    timeout: Duration,
+    start: Instant,
+    written: Vec<u8>,
+}
+
+impl Replay {
+    /// Play back `session`, failing with `io::ErrorKind::InvalidInput` if
+    /// `timing` is accelerated by a factor that isn't positive.
+    pub fn new(session: &Session, timing: Timing) -> io::Result<Self> {
+        if let Timing::Accelerated(factor) = timing {
+            if factor.is_nan() || factor <= 0.0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid replay acceleration {}", factor),
+                ));
+            }
+        }
+        let chunks = session.entries
+            .iter()
+            .filter(|entry| entry.direction == Direction::DeviceToHost && !entry.bytes.is_empty())
+            .map(|entry| (entry.at, entry.bytes.clone()))
+            .collect();
+        Ok(Replay {
+            chunks,
+            next: 0,
+            offset: 0,
+            timing,
+            timeout: Duration::from_millis(5),
+            start: Instant::now(),
+            written: Vec::new(),
+        })
+    }
+
+    /// Set how long a read may block waiting for the next chunk.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The bytes the host has written, to compare with the session.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// How long after the replay started a chunk recorded at `at` is due.
+    fn due(&self, at: Duration) -> Duration {
+        match self.timing {
+            Timing::Original => at,
+            // A tiny factor puts the chunk out of reach rather than overflowing.
+            Timing::Accelerated(factor) => {
+                Duration::try_from_secs_f64(at.as_secs_f64() / factor).unwrap_or(Duration::MAX)
+            }
+            Timing::Immediate => Duration::from_secs(0),
+        }
+    }
+}
+
+impl io::Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (at, len) = match self.chunks.get(self.next) {
+            Some(&(at, ref bytes)) => (at, bytes.len()),
+            None => return Ok(0),
+        };
+
+        let wait = self.due(at).saturating_sub(self.start.elapsed());
+        if wait > self.timeout {
+            thread::sleep(self.timeout);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Next replayed chunk is not due yet"));
+        }
+        thread::sleep(wait);
+
+        let count = buf.len().min(len - self.offset);
+        buf[..count].copy_from_slice(&self.chunks[self.next].1[self.offset..self.offset + count]);
+        self.offset += count;
+        if self.offset == len {
+            self.next += 1;
+            self.offset = 0;
+        }
+        Ok(count)
+    }
+}
+
+impl io::Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connection::Connection;
+    use emulator::Emulator;
+    use errors::*;
+
+    #[test]
+    fn records_and_replays_a_connection() {
+        let mut conn = Connection::new(Recorder::new(Emulator::uno(), Vec::new())).unwrap();
+        conn.resync().unwrap();
+        conn.initialize_with(Duration::from_millis(50)).unwrap();
+        let recorded = match conn {
+            Connection::Open { inner, .. } => inner.into_inner().1,
+            Connection::Closed => unreachable!(),
+        };
+
+        let session = Session::read(&recorded[..]).unwrap();
+        assert_eq!(session.bytes(Direction::HostToDevice)[..4], [0xFF, 0xF0, 0x79, 0xF7]);
+
+        let mut replayed = Connection::new(Replay::new(&session, Timing::Immediate).unwrap()).unwrap();
+        loop {
+            match replayed.update() {
+                Ok(_) => {}
+                Err(Error(ErrorKind::Io(ref e), _)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("Replay failed: {}", e),
+            }
+        }
+        let board = replayed.board().unwrap();
+        assert_eq!(board.firmware.as_ref().unwrap().name, "StandardFirmata.ino");
+        assert_eq!(board.pin_count(), 20);
+        assert_eq!(board.pins[&14].mode, Some(::protocol::PinMode::AnalogInput));
+    }
+
+    #[test]
+    fn replays_with_accelerated_timing() {
+        let session = Session::read(&b"# two reports\n0.000000 < F9 02 05\n0.200000 < E0 10 01\n"[..]).unwrap();
+        let mut replay = Replay::new(&session, Timing::Accelerated(10.0)).unwrap().timeout(Duration::from_millis(50));
+        let mut buf = [0; 8];
+
+        let start = Instant::now();
+        assert_eq!(io::Read::read(&mut replay, &mut buf).unwrap(), 3);
+        assert_eq!(io::Read::read(&mut replay, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], &[0xE0, 0x10, 0x01]);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(io::Read::read(&mut replay, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_times_and_factors() {
+        for line in &["inf < F9 02 05", "1e400 < F9 02 05", "-1 < F9 02 05", "NaN < F9 02 05"] {
+            assert_eq!(Entry::parse(line).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let session = Session::read(&b"0.5 < F9 02 05\n"[..]).unwrap();
+        for &factor in &[0.0, -2.0, f64::NAN] {
+            let err = Replay::new(&session, Timing::Accelerated(factor)).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let mut replay = Replay::new(&session, Timing::Accelerated(1e-300)).unwrap().timeout(Duration::from_millis(1));
+        let mut buf = [0; 8];
+        assert_eq!(io::Read::read(&mut replay, &mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn skips_empty_device_chunks() {
+        assert_eq!(Entry::parse("0.5 <").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let empty = Entry { at: Duration::ZERO, direction: Direction::DeviceToHost, bytes: Vec::new() };
+        let mut session = Session::read(&b"0 < F9 02 05\n"[..]).unwrap();
+        session.entries.insert(0, empty);
+        let mut replay = Replay::new(&session, Timing::Immediate).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(io::Read::read(&mut replay, &mut buf).unwrap(), 3);
+    }
+}
@@ -0,0 +1,102 @@
+//! Decoding a captured Firmata stream for display.
+//!
+//! Unlike `client::read`, a `Decoder` never gives up on a stream: bytes that
+//! don't parse are skipped one at a time until a message parses again, and
+//! are handed back so a tool can show where the stream lost sync.
+
+use ::parser::{self, Direction, Message};
+use nom;
+
+/// A piece of a decoded stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoded {
+    Message(Message),
+    /// Bytes skipped before the stream parsed again.
+    Unparseable(Vec<u8>),
+}
+
+/// Decodes the bytes sent in one direction of a link.
+#[derive(Debug)]
+pub struct Decoder {
+    direction: Direction,
+    buf: Vec<u8>,
+    skipped: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(direction: Direction) -> Self {
+        Decoder { direction, buf: Vec::new(), skipped: Vec::new() }
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Decode the messages completed by `bytes`.
+    ///
+    /// Unparseable bytes are returned as a single `Decoded::Unparseable`
+    /// just before the message that follows them.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Decoded> {
+        self.buf.extend_from_slice(bytes);
+        let mut decoded = Vec::new();
+        while !self.buf.is_empty() {
+            let consumed = match parser::parse_from(self.direction, &self.buf) {
+                Ok((rest, msg)) => {
+                    if !self.skipped.is_empty() {
+                        decoded.push(Decoded::Unparseable(::std::mem::take(&mut self.skipped)));
+                    }
+                    decoded.push(Decoded::Message(msg));
+                    self.buf.len() - rest.len()
+                }
+                Err(nom::Err::Incomplete(_)) => break,
+                Err(_) => {
+                    self.skipped.push(self.buf[0]);
+                    1
+                }
+            };
+            self.buf.drain(..consumed);
+        }
+        decoded
+    }
+
+    /// Take the bytes skipped or buffered so far, such as when the stream ends.
+    pub fn flush(&mut self) -> Option<Decoded> {
+        let mut rest = ::std::mem::take(&mut self.skipped);
+        rest.append(&mut self.buf);
+        if rest.is_empty() {
+            None
+        } else {
+            Some(Decoded::Unparseable(rest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::*;
+
+    #[test]
+    fn decodes_across_chunks_and_garbage() {
+        let mut decoder = Decoder::new(Direction::DeviceToHost);
+        assert_eq!(decoder.feed(&[0x12, 0x34, 0xF9, 0x02]), vec![]);
+        assert_eq!(
+            decoder.feed(&[0x05, 0xE1, 0x10]),
+            vec![
+                Decoded::Unparseable(vec![0x12, 0x34]),
+                Decoded::Message(Message::Device(FirmataMsg::ProtocolVersion { major: 2, minor: 5 })),
+            ]
+        );
+        assert_eq!(decoder.flush(), Some(Decoded::Unparseable(vec![0xE1, 0x10])));
+        assert_eq!(decoder.flush(), None);
+
+        let mut host = Decoder::new(Direction::HostToDevice);
+        assert_eq!(
+            host.feed(&[RESET, START_SYSEX, QUERY_FIRMWARE, END_SYSEX]),
+            vec![
+                Decoded::Message(Message::Host(HostCommand::Reset)),
+                Decoded::Message(Message::Host(HostCommand::QueryFirmware)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Independent reader and writer halves of a connection.
+//!
+//! A `Connection` owns a single stream, so a thread blocked reading from the
+//! device holds up every command the application wants to send. Splitting
+//! the connection gives the reading thread its own handle to the device and
+//! lets any number of threads send commands through a shared writer, with
+//! the `Board` shared between them.
+
+use ::board::Board;
+use ::connection::{Connection, RW};
+use ::errors::*;
+use ::events::{Event, Events};
+use ::protocol::PinMode;
+use ::reconnect;
+use ::tcp::TcpPort;
+use serial_unix;
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Transports that can hand out a second, independent handle for writing.
+pub trait Split: RW + Sized {
+    type Writer: io::Write + Send;
+
+    fn try_clone_writer(&self) -> io::Result<Self::Writer>;
+}
+
+impl Split for serial_unix::TTYPort {
+    type Writer = File;
+
+    fn try_clone_writer(&self) -> io::Result<File> {
+        // The port is borrowed for the duration of the call, so its file
+        // descriptor is open while it is duplicated.
+        let fd = unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) };
+        fd.try_clone_to_owned().map(File::from)
+    }
+}
+
+impl Split for TcpPort {
+    type Writer = TcpStream;
+
+    fn try_clone_writer(&self) -> io::Result<TcpStream> {
+        self.get_ref().try_clone()
+    }
+}
+
+/// The reading half of a split connection, which applies messages from the
+/// device to the shared board.
+pub struct Reader<T>
+where
+    T: RW
+{
+    inner: T,
+    board: Arc<Mutex<Board>>,
+}
+
+/// The writing half of a split connection. Clones share the same device.
+pub struct Writer<W>
+where
+    W: io::Write + Send
+{
+    inner: Arc<Mutex<W>>,
+    board: Arc<Mutex<Board>>,
+}
+
+impl<W> Clone for Writer<W>
+where
+    W: io::Write + Send
+{
+    fn clone(&self) -> Self {
+        Writer { inner: self.inner.clone(), board: self.board.clone() }
+    }
+}
+
+impl<T> Connection<T>
+where
+    T: Split
+{
+    /// Split the connection into a reader and a writer sharing the board.
+    pub fn split(self) -> Result<(Reader<T>, Writer<T::Writer>)> {
+        match self {
+            Connection::Open { inner, board } => {
+                let writer = inner.try_clone_writer()?;
+                let board = Arc::new(Mutex::new(board));
+                Ok((
+                    Reader { inner, board: board.clone() },
+                    Writer { inner: Arc::new(Mutex::new(writer)), board },
+                ))
+            }
+            Connection::Closed => Err(ErrorKind::ConnectionClosed.into()),
+        }
+    }
+}
+
+impl<T> Reader<T>
+where
+    T: RW
+{
+    pub fn board(&self) -> Arc<Mutex<Board>> {
+        self.board.clone()
+    }
+
+    /// Read a message from the device and apply it to the board, returning
+    /// the events it caused. The board is only locked while it is updated.
+    pub fn update(&mut self) -> Result<Vec<Event>> {
+        let msg = ::client::read(&mut self.inner)?;
+        Ok(self.board.lock().unwrap().update(msg))
+    }
+
+    /// Update the board and publish its events until the device goes away,
+    /// returning the error that ended the connection.
+    ///
+    /// Edges that settle within their debounce window are published after
+    /// the next update or read timeout, as the `Worker` does.
+    pub fn run(&mut self, events: &Events) -> Error {
+        loop {
+            match self.update() {
+                Ok(updates) => {
+                    for event in &updates {
+                        events.publish(event);
+                    }
+                }
+                Err(e) => {
+                    if reconnect::is_disconnect(&e) {
+                        events.publish(&Event::Disconnected);
+                        return e;
+                    }
+                    trace!("Serial read returned error {:?}", e);
+                }
+            }
+            let settled = self.board.lock().unwrap().poll_edges(Instant::now());
+            for event in &settled {
+                events.publish(event);
+            }
+        }
+    }
+}
+
+impl<W> Writer<W>
+where
+    W: io::Write + Send
+{
+    pub fn board(&self) -> Arc<Mutex<Board>> {
+        self.board.clone()
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        ::client::reset(&mut *self.inner.lock().unwrap())
+            .map_err(|e| e.into())
+    }
+
+    pub fn query_firmware(&self) -> Result<()> {
+        ::client::query_firmware(&mut *self.inner.lock().unwrap())
+            .map_err(|e| e.into())
+    }
+
+    pub fn query_version(&self) -> Result<()> {
+        ::client::query_version(&mut *self.inner.lock().unwrap())
+            .map_err(|e| e.into())
+    }
+
+    pub fn capabilities(&self) -> Result<()> {
+        ::client::capabilities(&mut *self.inner.lock().unwrap())
+            .map_err(|e| e.into())
+    }
+
+    pub fn set_pin_mode(&self, pin: u8, mode: PinMode) -> Result<()> {
+        ::client::set_pin_mode(&mut *self.inner.lock().unwrap(), pin, mode)?;
+        self.board.lock().unwrap().record_mode(pin, mode);
+        Ok(())
+    }
+
+    pub fn analog_report(&self, pin: u8, state: bool) -> Result<()> {
+        ::client::analog_report(&mut *self.inner.lock().unwrap(), pin, state)?;
+        self.board.lock().unwrap().record_analog_report(pin, state);
+        Ok(())
+    }
+
+    pub fn digital_report(&self, port: u8, state: bool) -> Result<()> {
+        ::client::digital_report(&mut *self.inner.lock().unwrap(), port, state)?;
+        self.board.lock().unwrap().record_digital_report(port, state);
+        Ok(())
+    }
+
+    pub fn digital_port_write(&self, port: u8, value: u8) -> Result<()> {
+        ::client::digital_port_write(&mut *self.inner.lock().unwrap(), port, value)?;
+        self.board.lock().unwrap().record_port_write(port, value);
+        Ok(())
+    }
+
+    /// Set the level of a single digital pin, as `Connection::digital_write` does.
+    pub fn digital_write(&self, pin: u8, level: bool) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut board = self.board.lock().unwrap();
+        if board.supports_pin_write() {
+            ::client::digital_write(&mut *inner, pin, level)?;
+        } else {
+            ::client::digital_port_write(&mut *inner, pin / 8, board.port_value_with(pin, level))?;
+        }
+        board.record_pin_write(pin, level);
+        Ok(())
+    }
+
+    pub fn analog_write(&self, pin: u8, value: u16) -> Result<()> {
+        ::client::analog_write(&mut *self.inner.lock().unwrap(), pin, value)?;
+        self.board.lock().unwrap().record_analog_write(pin, value);
+        Ok(())
+    }
+
+    pub fn i2c_config(&self, delay_us: u16) -> Result<()> {
+        ::client::i2c_config(&mut *self.inner.lock().unwrap(), delay_us)
+            .map_err(|e| e.into())
+    }
+
+    pub fn i2c_write(&self, address: u8, data: &[u8]) -> Result<()> {
+        ::client::i2c_write(&mut *self.inner.lock().unwrap(), address, data)
+            .map_err(|e| e.into())
+    }
+
+    /// Ask an I2C device for bytes. The reply is applied by the `Reader`,
+    /// which publishes it as `Event::I2cReply`.
+    pub fn i2c_read(&self, address: u8, register: Option<u16>, len: u16) -> Result<()> {
+        ::client::i2c_read(&mut *self.inner.lock().unwrap(), address, register, len)
+            .map_err(|e| e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn writes_while_reader_is_blocked() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let conn = Connection::connect_tcp(addr).unwrap();
+        let (mut device, _) = listener.accept().unwrap();
+
+        let (mut reader, writer) = conn.split().unwrap();
+        let events = Events::new();
+        let read_thread = thread::spawn(move || reader.run(&events));
+
+        writer.analog_report(2, true).unwrap();
+        writer.clone().digital_port_write(1, 0x20).unwrap();
+
+        let mut sent = [0; 5];
+        device.read_exact(&mut sent).unwrap();
+        assert_eq!(sent, [ANALOG_REPORT | 2, 1, DIGITAL_MESSAGE << 4 | 1, 0x20, 0]);
+        assert!(writer.board().lock().unwrap().analog_reports.contains(&2));
+
+        drop(device);
+        read_thread.join().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reader_reports_edges_that_settle_within_debounce_window() {
+        use edges::{Edge, Trigger};
+        use emulator::Emulator;
+        use events::{EventKind, Filter};
+        use pty::VirtualBoard;
+        use std::time::Duration;
+
+        let device = VirtualBoard::spawn(Emulator::uno()).unwrap();
+        let conn = ::connection::ConnectionBuilder::new().open(device.path().to_str().unwrap()).unwrap();
+        let (mut reader, writer) = conn.split().unwrap();
+        writer.set_pin_mode(5, PinMode::DigitalInput).unwrap();
+        writer.digital_report(0, true).unwrap();
+        reader.board().lock().unwrap().watch_edges(5, Trigger::Both, Some(Duration::from_millis(100)));
+
+        let emulator = device.device();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while emulator.pin_mode(5) != Some(PinMode::DigitalInput) || !emulator.is_digital_reporting(0) {
+            assert!(Instant::now() < deadline, "pin 5 was not configured");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let events = Events::new();
+        let edges = events.subscribe(Filter::new().kind(EventKind::Edge));
+        let read_thread = {
+            let events = events.clone();
+            thread::spawn(move || reader.run(&events))
+        };
+        let edge = || match edges.recv_timeout(Duration::from_secs(1)) {
+            Ok(Event::Edge { pin, edge, .. }) => (pin, edge),
+            other => panic!("Expected an edge, got {:?}", other),
+        };
+
+        emulator.set_digital(5, true);
+        assert_eq!(edge(), (5, Edge::Rising));
+        // The release comes within the debounce window and is only reported
+        // once the window ends, while the line is idle.
+        emulator.set_digital(5, false);
+        assert_eq!(edge(), (5, Edge::Falling));
+
+        drop(device);
+        read_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! TCP transport for boards running EthernetFirmata or WiFiFirmata.
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time;
+
+/// The port that the Ethernet and WiFi Firmata sketches listen on.
+pub const DEFAULT_PORT: u16 = 3030;
+
+/// A TCP stream to a networked Firmata device.
+///
+/// Expired read timeouts are reported as `io::ErrorKind::TimedOut`, the same
+/// way a serial port reports them, so `client::read_rt` retries them instead
+/// of treating them as a failed read.
+pub struct TcpPort {
+    inner: TcpStream,
+}
+
+impl TcpPort {
+    pub fn connect<A>(addr: A, timeout: time::Duration) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let inner = TcpStream::connect(addr)?;
+        inner.set_nodelay(true)?;
+        inner.set_read_timeout(Some(timeout))?;
+        Ok(TcpPort { inner })
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.inner
+    }
+}
+
+impl From<TcpStream> for TcpPort {
+    /// Wrap an accepted stream. Its read timeout is left as it was set.
+    fn from(inner: TcpStream) -> Self {
+        TcpPort { inner }
+    }
+}
+
+impl io::Read for TcpPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|err| match err.kind() {
+            // Unix platforms report an expired socket timeout as EAGAIN.
+            io::ErrorKind::WouldBlock => io::Error::new(io::ErrorKind::TimedOut, err),
+            _ => err,
+        })
+    }
+}
+
+impl io::Write for TcpPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connection::Connection;
+    use errors::*;
+    use protocol::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn resyncs_and_updates_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&[PROTOCOL_VERSION, 2, 6]).unwrap();
+            stream.write_all(b"\xF0\x79\x02\x06WiFiFirmata\xF7").unwrap();
+            request
+        });
+
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        conn.resync().unwrap();
+        conn.update().unwrap();
+
+        assert_eq!(
+            device.join().unwrap(),
+            [RESET, START_SYSEX, QUERY_FIRMWARE, END_SYSEX]
+        );
+        let firmware = conn.board().unwrap().firmware.as_ref().unwrap();
+        assert_eq!(firmware.name, "WiFiFirmata");
+    }
+
+    #[test]
+    fn idle_socket_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        let _stream = listener.accept().unwrap();
+
+        match conn.read() {
+            Err(Error(ErrorKind::Io(ref err), _)) => {
+                assert_eq!(err.kind(), io::ErrorKind::TimedOut)
+            }
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn closed_socket_is_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut conn = Connection::connect_tcp(addr).unwrap();
+        drop(listener.accept().unwrap());
+
+        match conn.read() {
+            Err(Error(ErrorKind::Io(ref err), _)) => {
+                assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof)
+            }
+            other => panic!("Expected end of stream, got {:?}", other),
+        }
+    }
+}
@@ -1,28 +1,307 @@
 //! A worker thread that will update the state of a connection as long
 //! as that connection is open.
 
-use std::{thread, time};
+use std::{io, thread, time};
 use std::sync::{Arc, Mutex, Weak};
-use ::connection::Connection;
-use serial_unix;
+use std::sync::atomic::{AtomicBool, Ordering};
+use ::connection::{Connection, RW};
+use ::errors::*;
+use ::events::{Event, Events, Filter};
+use ::reconnect::{self, Reconnect};
 
-pub struct Worker {
-    inner: Weak<Mutex<Connection<serial_unix::TTYPort>>>
+/// The default delay between updates of the connection.
+pub const DEFAULT_INTERVAL: time::Duration = time::Duration::from_millis(20);
+
+type ErrorCallback = Box<dyn FnMut(&Error) + Send>;
+
+pub struct Worker<T>
+where
+    T: RW
+{
+    inner: Weak<Mutex<Connection<T>>>,
+    interval: time::Duration,
+    on_error: Option<ErrorCallback>,
+    reconnect: Option<Box<dyn Reconnect<T>>>,
+    shutdown: Arc<AtomicBool>,
+    events: Events,
+}
+
+/// A handle to a running worker thread.
+pub struct WorkerHandle {
+    shutdown: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+    events: Events,
+}
+
+impl WorkerHandle {
+    /// Receive the board events matching `filter` as the worker sees them.
+    pub fn subscribe(&self, filter: Filter) -> ::std::sync::mpsc::Receiver<Event> {
+        self.events.subscribe(filter)
+    }
+
+    /// The subscribers the worker publishes to, for sharing with other threads.
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    /// Ask the worker to stop and wait for it to finish its current update.
+    pub fn shutdown(self) -> thread::Result<()> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.thread.join()
+    }
+
+    /// Wait for the worker to stop on its own, which happens when the
+    /// connection is dropped or can't be reestablished.
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
 }
 
-impl Worker {
-    pub fn spawn(ref conn: &Arc<Mutex<Connection<serial_unix::TTYPort>>>) -> thread::JoinHandle<()> {
-        let inner = Arc::downgrade(&conn);
-        thread::spawn(move || Worker { inner }.run())
+impl<T> Worker<T>
+where
+    T: RW + 'static
+{
+    pub fn new(conn: &Arc<Mutex<Connection<T>>>) -> Self {
+        Worker {
+            inner: Arc::downgrade(conn),
+            interval: DEFAULT_INTERVAL,
+            on_error: None,
+            reconnect: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            events: Events::new(),
+        }
+    }
+
+    /// Spawn a worker with the default settings.
+    pub fn spawn(conn: &Arc<Mutex<Connection<T>>>) -> WorkerHandle {
+        Worker::new(conn).start()
     }
 
-    pub fn run(&self) {
-        while let Some(lock) = self.inner.upgrade() {
-            {
+    /// Spawn a worker that reopens the connection when the device disappears.
+    ///
+    /// While the device is gone the connection is `Connection::Closed`, so
+    /// commands issued by the application fail with `ConnectionClosed`.
+    pub fn spawn_with_reconnect<R>(conn: &Arc<Mutex<Connection<T>>>, reconnect: R) -> WorkerHandle
+    where
+        R: Reconnect<T> + 'static
+    {
+        Worker::new(conn).reconnect(reconnect).start()
+    }
+
+    /// Set the delay between updates of the connection.
+    pub fn interval(mut self, interval: time::Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Call `f` with every error returned while updating the connection.
+    ///
+    /// Read timeouts only mean that the device had nothing to say and are not
+    /// reported. Without a callback errors are logged.
+    pub fn on_error<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Error) + Send + 'static
+    {
+        self.on_error = Some(Box::new(f));
+        self
+    }
+
+    /// Reestablish the connection with `reconnect` when the device disappears.
+    pub fn reconnect<R>(mut self, reconnect: R) -> Self
+    where
+        R: Reconnect<T> + 'static
+    {
+        self.reconnect = Some(Box::new(reconnect));
+        self
+    }
+
+    /// Publish board events to `events` instead of a new set of subscribers.
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Run the worker on a new thread.
+    pub fn start(mut self) -> WorkerHandle {
+        let shutdown = self.shutdown.clone();
+        let events = self.events.clone();
+        let thread = thread::spawn(move || self.run());
+        WorkerHandle { shutdown, thread, events }
+    }
+
+    /// Update the connection until it is dropped or closed, the worker is
+    /// shut down, or the device disappears and can't be reconnected.
+    pub fn run(&mut self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            let lock = match self.inner.upgrade() {
+                Some(lock) => lock,
+                None => break,
+            };
+
+            let (result, settled) = {
                 let mut conn = lock.lock().unwrap();
-                conn.update();
+                let result = conn.update();
+                let settled = conn.board_mut()
+                    .map(|board| board.poll_edges(time::Instant::now()))
+                    .unwrap_or_default();
+                (result, settled)
+            };
+            for event in &settled {
+                self.events.publish(event);
+            }
+            match result {
+                Ok(events) => {
+                    for event in &events {
+                        self.events.publish(event);
+                    }
+                }
+                Err(ref e) if is_timeout(e) => {}
+                // Only the application closes the connection; a lost device
+                // leaves it open with a broken stream.
+                Err(Error(ErrorKind::ConnectionClosed, _)) => {
+                    debug!("Firmata connection was closed, stopping the worker");
+                    break;
+                }
+                Err(e) => {
+                    self.report(&e);
+                    if reconnect::is_disconnect(&e) {
+                        self.events.publish(&Event::Disconnected);
+                        if !self.reopen(&lock, &e) {
+                            break;
+                        }
+                    }
+                }
             }
-            thread::sleep(time::Duration::from_millis(20));
+
+            drop(lock);
+            thread::sleep(self.interval);
         }
     }
+
+    /// Replace a lost connection, returning whether the worker can carry on.
+    fn reopen(&self, lock: &Mutex<Connection<T>>, err: &Error) -> bool {
+        let reconnect = match self.reconnect {
+            Some(ref reconnect) => reconnect,
+            None => return false,
+        };
+
+        warn!("Firmata connection lost, reconnecting: {}", err);
+        // Release the lock while waiting on the device so the
+        // application isn't blocked for the whole backoff.
+        let board = lock.lock().unwrap().close().unwrap_or_default();
+        match reconnect.reopen(board) {
+            Ok(conn) => {
+                *lock.lock().unwrap() = conn;
+                true
+            }
+            Err(e) => {
+                error!("Unable to reconnect to Firmata device: {}", e);
+                false
+            }
+        }
+    }
+
+    fn report(&mut self, err: &Error) {
+        match self.on_error {
+            Some(ref mut f) => f(err),
+            None => warn!("Unable to update Firmata connection: {}", err),
+        }
+    }
+}
+
+fn is_timeout(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Io(ref e) => e.kind() == io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use events::EventKind;
+    use board::Board;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use tcp::TcpPort;
+
+    #[test]
+    fn updates_board_and_reports_errors() {
+        let stream = Cursor::new(b"\xF9\x02\x06".to_vec());
+        let conn = Arc::new(Mutex::new(Connection::new(stream).unwrap()));
+
+        let (tx, rx) = mpsc::channel();
+        let handle = Worker::new(&conn)
+            .interval(time::Duration::from_millis(1))
+            .on_error(move |e| { let _ = tx.send(e.to_string()); })
+            .start();
+
+        // The cursor runs dry after the first message.
+        let err = rx.recv_timeout(time::Duration::from_secs(1)).unwrap();
+        assert!(err.contains("stream closed"), "{}", err);
+        handle.join().unwrap();
+
+        let conn = conn.lock().unwrap();
+        assert!(conn.board().unwrap().protocol.is_some());
+    }
+
+    #[test]
+    fn publishes_board_events() {
+        let stream = Cursor::new(b"\xE3\x10\x01\xE3\x10\x01\xE3\x11\x01".to_vec());
+        let conn = Arc::new(Mutex::new(Connection::new(stream).unwrap()));
+
+        let events = Events::new();
+        let pin_3 = events.subscribe(Filter::new().pin(3));
+        let disconnects = events.subscribe(Filter::new().kind(EventKind::Disconnected));
+        let handle = Worker::new(&conn)
+            .interval(time::Duration::from_millis(1))
+            .on_error(|_| {})
+            .events(events)
+            .start();
+        handle.join().unwrap();
+
+        assert_eq!(
+            pin_3.try_iter().collect::<Vec<_>>(),
+            vec![
+                Event::PinChanged { pin: 3, value: 144 },
+                Event::PinChanged { pin: 3, value: 145 },
+            ]
+        );
+        assert_eq!(disconnects.try_iter().count(), 1);
+    }
+
+    struct Refuse(mpsc::Sender<()>);
+
+    impl Reconnect<TcpPort> for Refuse {
+        fn reopen(&self, _: Board) -> Result<Connection<TcpPort>> {
+            let _ = self.0.send(());
+            Err(ErrorKind::ConnectionClosed.into())
+        }
+    }
+
+    #[test]
+    fn stops_without_reconnecting_when_connection_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let conn = Connection::connect_tcp(listener.local_addr().unwrap()).unwrap();
+        let _device = listener.accept().unwrap();
+        let conn = Arc::new(Mutex::new(conn));
+
+        let (tx, reopened) = mpsc::channel();
+        let handle = Worker::new(&conn)
+            .interval(time::Duration::from_millis(1))
+            .reconnect(Refuse(tx))
+            .start();
+        conn.lock().unwrap().close();
+        handle.join().unwrap();
+        assert!(reopened.try_recv().is_err(), "the worker reopened a closed connection");
+    }
+
+    #[test]
+    fn stops_when_connection_is_dropped() {
+        let conn = Arc::new(Mutex::new(Connection::new(Cursor::new(Vec::new())).unwrap()));
+        let handle = Worker::new(&conn).on_error(|_| {}).start();
+        drop(conn);
+        handle.join().unwrap();
+    }
 }